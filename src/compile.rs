@@ -1,14 +1,27 @@
 use std::fs;
-use pest::Parser;
+use pest::{ iterators::Pair, Parser };
 use pest_derive::Parser;
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 use std::time::Instant;
 use colored::*;
 use chrono::Local;
 
-use crate::{ error::{ RCSSError, display_error }, Result };
-
-use crate::{ rule_normal, variables, functions, keyframes, imports, media_queries, MetaData };
+use crate::{ error::{ RCSSError, display_error, get_error_context }, Result };
+
+use crate::{
+    rule_normal,
+    variables,
+    functions,
+    keyframes,
+    imports,
+    media_queries,
+    control_flow,
+    loops,
+    extends,
+    traits,
+    value::Value,
+    MetaData,
+};
 
 #[derive(Parser)]
 #[grammar = "rcss.pest"]
@@ -19,17 +32,103 @@ pub fn print_rule(pair: pest::iterators::Pair<Rule>) {
     println!("{:?} -> {}", pair.as_rule(), pair.as_str());
 }
 
+/// A top level statement waiting to be processed
+enum Statement<'a> {
+    Pair(Pair<'a, Rule>),
+
+    /// Starts an iteration of a `for` loop by declaring its variables
+    BeginIteration(Vec<(String, Value)>),
+
+    /// Drops everything declared since the iteration started
+    EndIteration,
+}
+
+/// The CSS a file compiles to, before it is written out
+#[derive(Default)]
+struct CompiledFile {
+    declarations: HashMap<String, Vec<String>>,
+    keyframes: HashMap<String, HashMap<String, Vec<String>>>,
+    one_liners: Vec<String>,
+    inlined_css: Vec<(String, String)>,
+    media_queries: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
 #[allow(unused)]
+#[allow(clippy::too_many_arguments)]
 pub fn compile(
     input_path: &str,
     output_path: &str,
     relative_path: &str,
     project_meta_data: &mut HashMap<String, Vec<MetaData>>,
+    failed_files: &mut HashSet<String>,
     verbose: bool,
-    initial_compile: bool
+    initial_compile: bool,
+    max_iterations: usize
 ) -> Result<HashMap<String, Vec<MetaData>>> {
     let start_time = Instant::now();
 
+    let mut included = vec![input_path.to_string()];
+    let mut import_stack = Vec::new();
+
+    let compiled = compile_file(
+        input_path,
+        relative_path,
+        project_meta_data,
+        failed_files,
+        initial_compile,
+        max_iterations,
+        &mut included,
+        &mut import_stack
+    )?;
+
+    let now = Local::now();
+    let formatted_time = now.format("%I:%M:%S %p");
+
+    let elapsed_time = start_time.elapsed();
+
+    if initial_compile {
+        return Ok(project_meta_data.clone());
+    }
+
+    let css_output = css_map_to_string(
+        &compiled.declarations,
+        &compiled.keyframes,
+        &compiled.one_liners,
+        &compiled.inlined_css,
+        &compiled.media_queries
+    );
+
+    // Create folders
+    if let Some(parent) = std::path::Path::new(output_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(output_path, css_output)?;
+
+    println!(
+        "{} {} {}",
+        format!("CSS written to {}", output_path).green(),
+        format!("in {:.2?}", elapsed_time).truecolor(128, 128, 128),
+        format!("@ {}", formatted_time).truecolor(128, 128, 128)
+    );
+
+    Ok(project_meta_data.clone())
+}
+
+/// Compiles a file into its rules and fills its entry in `project_meta_data`.
+/// `included` lists the files whose rules are already part of the output, so
+/// including a file twice only adds its rules once. Files in `failed_files` have
+/// already shown their errors and are not compiled again for each importer
+#[allow(clippy::too_many_arguments)]
+fn compile_file(
+    input_path: &str,
+    relative_path: &str,
+    project_meta_data: &mut HashMap<String, Vec<MetaData>>,
+    failed_files: &mut HashSet<String>,
+    initial_compile: bool,
+    max_iterations: usize,
+    included: &mut Vec<String>,
+    import_stack: &mut Vec<String>
+) -> Result<CompiledFile> {
     let raw_rcss = fs::read_to_string(input_path)?;
 
     let pairs = match RCSSParser::parse(Rule::rcss, &raw_rcss) {
@@ -61,19 +160,129 @@ pub fn compile(
         }
     };
 
-    let mut css_output = String::new();
     let mut meta_data: Vec<MetaData> = Vec::new();
     let mut declarations: HashMap<String, Vec<String>> = HashMap::new();
     let mut keyframes: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
     let mut one_liners: Vec<String> = Vec::new();
+    let mut inlined_css: Vec<(String, String)> = Vec::new();
     let mut media_queries: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+    let mut extend_statements: Vec<extends::Extend> = Vec::new();
+
+    // The files this one reads items from are compiled first, so their items
+    // (and the ones they re-export with pub use) are known before it needs them
+    import_stack.push(input_path.to_string());
+
+    for (dependency, line_col) in imports::dependencies(pairs.clone(), input_path, relative_path) {
+        let mut chain = import_stack.clone();
+
+        if
+            let Some((file, (line, column))) = find_import_cycle(
+                &dependency,
+                line_col,
+                input_path,
+                relative_path,
+                &mut chain,
+                &mut Vec::new()
+            )
+        {
+            let source = fs::read_to_string(&file).unwrap_or_default();
+            let cycle: Vec<String> = chain
+                .iter()
+                .map(|file| imports::module_name(file, relative_path))
+                .collect();
+
+            let err = RCSSError::ImportError {
+                file_path: file.into(),
+                line,
+                column,
+                message: format!("Circular import: {}", cycle.join(" -> ")),
+                context: get_error_context(&source, line, 2),
+            };
+
+            display_error(&err);
+            return Err(err);
+        }
+
+        if project_meta_data.contains_key(&dependency) {
+            continue;
+        }
+
+        // Its error was already shown, this file can't compile without it either
+        if failed_files.contains(&dependency) {
+            return Err(RCSSError::ImportError {
+                file_path: input_path.into(),
+                line: line_col.0,
+                column: line_col.1,
+                message: format!("{} has errors", imports::module_name(&dependency, relative_path)),
+                context: get_error_context(&raw_rcss, line_col.0, 2),
+            });
+        }
+
+        let compiled = compile_file(
+            &dependency,
+            relative_path,
+            project_meta_data,
+            failed_files,
+            true,
+            max_iterations,
+            &mut Vec::new(),
+            import_stack
+        );
+
+        if let Err(err) = compiled {
+            failed_files.insert(dependency);
+            return Err(err);
+        }
+    }
+
+    // Items named by path (ex: &colors::primary) are looked up like imports
+    meta_data = imports::import_qualified_references(
+        meta_data,
+        project_meta_data,
+        &raw_rcss,
+        input_path,
+        relative_path,
+        pairs.clone()
+    )?;
+
+    // The statements inside a taken top level if/match branch or a loop are processed in its place
+    let mut pending: Vec<Statement> = pairs.rev().map(Statement::Pair).collect();
+    let mut iteration_scopes: Vec<usize> = Vec::new();
+
+    while let Some(statement) = pending.pop() {
+        let pair = match statement {
+            Statement::Pair(pair) => pair,
+
+            Statement::BeginIteration(variables) => {
+                iteration_scopes.push(meta_data.len());
+
+                for (name, value) in variables {
+                    meta_data.push(MetaData::Variables { name, value, public: false });
+                }
+                continue;
+            }
+
+            Statement::EndIteration => {
+                if let Some(scope_start) = iteration_scopes.pop() {
+                    meta_data.truncate(scope_start);
+                }
+                continue;
+            }
+        };
 
-    for pair in pairs {
         match pair.as_rule() {
             Rule::import_statement => {
-                // we don't want to import anything on initial check
-                // we just want to fill project_meta_data
-                if initial_compile {
+                // Plain CSS files are copied into the output once, in the order they are used
+                if
+                    let Some((css_path, css)) = imports::process_css_import(
+                        &pair,
+                        input_path,
+                        relative_path
+                    )?
+                {
+                    if !inlined_css.iter().any(|(path, _)| *path == css_path) {
+                        inlined_css.push((css_path, css));
+                    }
                     continue;
                 }
 
@@ -87,8 +296,82 @@ pub fn compile(
                 )?;
             }
 
+            Rule::include_statement => {
+                let target = imports::resolve_include(
+                    &pair,
+                    &raw_rcss,
+                    input_path,
+                    relative_path
+                )?;
+
+                // The file's public items come along as with use
+                meta_data = imports::process_import_statement(
+                    &mut meta_data,
+                    project_meta_data,
+                    &raw_rcss,
+                    input_path,
+                    relative_path,
+                    pair
+                )?;
+
+                // Rules are only needed once the file is really compiled
+                if initial_compile || included.contains(&target) {
+                    continue;
+                }
+
+                included.push(target.clone());
+
+                let compiled = compile_file(
+                    &target,
+                    relative_path,
+                    project_meta_data,
+                    failed_files,
+                    false,
+                    max_iterations,
+                    included,
+                    import_stack
+                )?;
+
+                for (selector, properties) in compiled.declarations {
+                    declarations.entry(selector).or_default().extend(properties);
+                }
+
+                for (at_rule, frames) in compiled.keyframes {
+                    let current = keyframes.entry(at_rule).or_default();
+
+                    for (keyframe_selector, properties) in frames {
+                        current.entry(keyframe_selector).or_default().extend(properties);
+                    }
+                }
+
+                for (condition, rules) in compiled.media_queries {
+                    let current = media_queries.entry(condition).or_default();
+
+                    for (selector, properties) in rules {
+                        current.entry(selector).or_default().extend(properties);
+                    }
+                }
+
+                for one_liner in compiled.one_liners {
+                    if !one_liners.contains(&one_liner) {
+                        one_liners.push(one_liner);
+                    }
+                }
+
+                for (css_path, css) in compiled.inlined_css {
+                    if !inlined_css.iter().any(|(path, _)| *path == css_path) {
+                        inlined_css.push((css_path, css));
+                    }
+                }
+            }
+
             Rule::variable_declaration => {
-                meta_data = variables::process_variable_declaration(meta_data, pair);
+                meta_data = variables::process_variable_declaration(
+                    meta_data,
+                    pair,
+                    &raw_rcss,
+                    input_path
+                )?;
             }
 
             Rule::function_definition => {
@@ -101,16 +384,27 @@ pub fn compile(
                 )?;
             }
 
-            Rule::rule_normal => {
+            Rule::trait_definition => {
+                meta_data = traits::process_trait_definition(
+                    meta_data,
+                    pair,
+                    &raw_rcss,
+                    input_path
+                )?;
+            }
+
+            Rule::rule_normal | Rule::impl_statement => {
                 // we don't want to import anything on initial check
                 // we just want to fill project_meta_data
                 if initial_compile {
                     continue;
                 }
 
-                declarations = rule_normal::process_rule_normal(
+                (declarations, media_queries) = rule_normal::process_rule_normal(
                     meta_data.clone(),
                     declarations,
+                    media_queries,
+                    None,
                     pair,
                     &raw_rcss,
                     &input_path
@@ -131,6 +425,44 @@ pub fn compile(
                 )?;
             }
 
+            Rule::top_if_statement | Rule::top_match_statement => {
+                let branch = control_flow::select_branch(pair, &meta_data, &raw_rcss, input_path)?;
+
+                if let Some(block) = branch {
+                    pending.extend(block.into_inner().rev().map(Statement::Pair));
+                }
+            }
+
+            Rule::for_statement => {
+                let for_loop = loops::process_for_statement(
+                    pair,
+                    &meta_data,
+                    &raw_rcss,
+                    input_path,
+                    max_iterations
+                )?;
+
+                for values in for_loop.iterations.into_iter().rev() {
+                    pending.push(Statement::EndIteration);
+                    pending.extend(for_loop.body.clone().into_inner().rev().map(Statement::Pair));
+                    pending.push(
+                        Statement::BeginIteration(
+                            for_loop.variables.iter().cloned().zip(values).collect()
+                        )
+                    );
+                }
+            }
+
+            Rule::extend_statement => {
+                if initial_compile {
+                    continue;
+                }
+
+                extend_statements.push(
+                    extends::process_extend_statement(pair, &meta_data, &raw_rcss, input_path)?
+                );
+            }
+
             Rule::rule_comment => {}
 
             Rule::EOI => {}
@@ -160,38 +492,78 @@ pub fn compile(
     }
 
     project_meta_data.insert(input_path.to_string(), meta_data.clone());
+    import_stack.pop();
 
-    let now = Local::now();
-    let formatted_time = now.format("%I:%M:%S %p");
+    if initial_compile {
+        return Ok(CompiledFile::default());
+    }
 
-    let elapsed_time = start_time.elapsed();
+    // Inheritance is applied last so it sees rules written after the impl statement
+    (declarations, media_queries) = extends::apply_extends(
+        declarations,
+        media_queries,
+        &extend_statements,
+        &raw_rcss,
+        input_path
+    )?;
 
-    if initial_compile {
-        return Ok(project_meta_data.clone());
+    Ok(CompiledFile { declarations, keyframes, one_liners, inlined_css, media_queries })
+}
+
+/// The project files `file` imports or includes, read from disk without compiling it
+pub fn file_dependencies(file: &str, relative_path: &str) -> Vec<(String, (usize, usize))> {
+    let Ok(raw_rcss) = fs::read_to_string(file) else {
+        return Vec::new();
+    };
+
+    match RCSSParser::parse(Rule::rcss, &raw_rcss) {
+        Ok(pairs) => imports::dependencies(pairs, file, relative_path),
+        Err(_) => Vec::new(),
     }
+}
 
-    let css_output = css_map_to_string(&declarations, &keyframes, &one_liners, &media_queries);
+/// Follows the imports of `file` looking for a way back to a file in `chain`
+/// (the files being compiled). On a cycle, `chain` holds it from the first
+/// repeated file and the file and position of the import closing it are returned
+fn find_import_cycle(
+    file: &str,
+    line_col: (usize, usize),
+    importer: &str,
+    relative_path: &str,
+    chain: &mut Vec<String>,
+    visited: &mut Vec<String>
+) -> Option<(String, (usize, usize))> {
+    if let Some(start) = chain.iter().position(|known| known == file) {
+        chain.drain(..start);
+        chain.push(file.to_string());
+        return Some((importer.to_string(), line_col));
+    }
 
-    // Create folders
-    if let Some(parent) = std::path::Path::new(output_path).parent() {
-        fs::create_dir_all(parent)?;
+    if visited.iter().any(|known| known == file) {
+        return None;
     }
-    fs::write(output_path, css_output)?;
 
-    println!(
-        "{} {} {}",
-        format!("CSS written to {}", output_path).green(),
-        format!("in {:.2?}", elapsed_time).truecolor(128, 128, 128),
-        format!("@ {}", formatted_time).truecolor(128, 128, 128)
-    );
+    visited.push(file.to_string());
 
-    Ok(project_meta_data.clone())
+    chain.push(file.to_string());
+
+    for (dependency, line_col) in file_dependencies(file, relative_path) {
+        let cycle = find_import_cycle(&dependency, line_col, file, relative_path, chain, visited);
+
+        if cycle.is_some() {
+            return cycle;
+        }
+    }
+
+    chain.pop();
+    None
 }
 
 fn css_map_to_string(
     css_map: &HashMap<String, Vec<String>>,
     keyframes: &HashMap<String, HashMap<String, Vec<String>>>,
     one_liners: &Vec<String>,
+    inlined_css: &[(String, String)],
     media_queries: &HashMap<String, HashMap<String, Vec<String>>>
 ) -> String {
     let mut css_string = String::new();
@@ -209,6 +581,11 @@ fn css_map_to_string(
         css_string.push_str("\n");
     }
 
+    for (_, css) in inlined_css {
+        css_string.push_str(css);
+        css_string.push_str("\n\n");
+    }
+
     for (selector, properties) in sorted_css_map {
         css_string.push_str(selector);
         css_string.push_str(" {\n");
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use crate::compile::file_dependencies;
+
+/// Which project files each file imports or includes, so the watcher knows
+/// what to rebuild when a shared file changes
+#[derive(Default)]
+pub struct DependencyGraph {
+    dependencies: HashMap<String, Vec<String>>,
+}
+
+impl DependencyGraph {
+    /// Re-reads the imports of `file`, called whenever it is compiled or saved
+    pub fn update(&mut self, file: &str, relative_path: &str) {
+        let dependencies = file_dependencies(file, relative_path)
+            .into_iter()
+            .map(|(dependency, _)| dependency)
+            .collect();
+
+        self.dependencies.insert(file.to_string(), dependencies);
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.dependencies.remove(file);
+    }
+
+    /// Every file that uses `file`, directly or through other imports, ordered so
+    /// each one comes after the files it imports
+    pub fn dependents(&self, file: &str) -> Vec<String> {
+        let mut affected: Vec<String> = Vec::new();
+        let mut pending = vec![file.to_string()];
+
+        while let Some(current) = pending.pop() {
+            for (dependent, dependencies) in &self.dependencies {
+                if
+                    dependencies.contains(&current) &&
+                    dependent != file &&
+                    !affected.contains(dependent)
+                {
+                    affected.push(dependent.clone());
+                    pending.push(dependent.clone());
+                }
+            }
+        }
+
+        affected.sort();
+
+        let mut ordered = Vec::new();
+        let mut visited = Vec::new();
+
+        for dependent in &affected {
+            self.visit(dependent, &affected, &mut visited, &mut ordered);
+        }
+
+        ordered
+    }
+
+    // Depth first, so a file is only pushed once the affected files it imports are
+    fn visit(
+        &self,
+        file: &str,
+        affected: &[String],
+        visited: &mut Vec<String>,
+        ordered: &mut Vec<String>
+    ) {
+        if visited.iter().any(|known| known == file) {
+            return;
+        }
+
+        visited.push(file.to_string());
+
+        for dependency in self.dependencies.get(file).into_iter().flatten() {
+            if affected.contains(dependency) {
+                self.visit(dependency, affected, visited, ordered);
+            }
+        }
+
+        ordered.push(file.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        DependencyGraph {
+            dependencies: edges
+                .iter()
+                .map(|(file, dependencies)| {
+                    (file.to_string(), dependencies.iter().map(|d| d.to_string()).collect())
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn orders_dependents_after_their_imports() {
+        let graph = graph(&[
+            ("common", &[]),
+            ("a", &["theme"]),
+            ("b", &["common"]),
+            ("theme", &["common"]),
+            ("unrelated", &[]),
+        ]);
+
+        assert_eq!(graph.dependents("common"), ["theme", "a", "b"]);
+        assert_eq!(graph.dependents("theme"), ["a"]);
+        assert!(graph.dependents("a").is_empty());
+    }
+
+    #[test]
+    fn stops_at_cycles() {
+        let graph = graph(&[("a", &["b"]), ("b", &["a"]), ("c", &["b"])]);
+
+        assert_eq!(graph.dependents("a"), ["b", "c"]);
+    }
+
+    #[test]
+    fn forgets_removed_files() {
+        let mut graph = graph(&[("a", &["common"]), ("b", &["common"])]);
+        graph.remove("a");
+
+        assert_eq!(graph.dependents("common"), ["b"]);
+    }
+}
@@ -42,6 +42,29 @@ pub enum RCSSError {
         message: String,
         context: String,
     },
+    ExpressionError {
+        file_path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+        context: String,
+    },
+    TraitError {
+        file_path: PathBuf,
+        line: usize,
+        column: usize,
+        trait_name: String,
+        message: String,
+        context: String,
+    },
+    SelectorError {
+        file_path: PathBuf,
+        line: usize,
+        column: usize,
+        selector: String,
+        message: String,
+        context: String,
+    },
 }
 
 impl fmt::Display for RCSSError {
@@ -104,6 +127,41 @@ impl fmt::Display for RCSSError {
                     context
                 )
             }
+            RCSSError::ExpressionError { file_path, line, column, message, context } => {
+                write!(
+                    f,
+                    "Expression Error at {}:{}:{} - {} (Context: {})",
+                    file_path.display(),
+                    line,
+                    column,
+                    message,
+                    context
+                )
+            }
+            RCSSError::TraitError { file_path, line, column, trait_name, message, context } => {
+                write!(
+                    f,
+                    "Trait Error for trait: {} at {}:{}:{} - {} (Context: {})",
+                    trait_name,
+                    file_path.display(),
+                    line,
+                    column,
+                    message,
+                    context
+                )
+            }
+            RCSSError::SelectorError { file_path, line, column, selector, message, context } => {
+                write!(
+                    f,
+                    "Selector Error for selector: {} at {}:{}:{} - {} (Context: {})",
+                    selector,
+                    file_path.display(),
+                    line,
+                    column,
+                    message,
+                    context
+                )
+            }
         }
     }
 }
@@ -168,6 +226,9 @@ pub fn display_error(error: &RCSSError) {
         RCSSError::ImportError { .. } => "IMPORT ERROR",
         RCSSError::VariableError { .. } => "VARIABLE ERROR",
         RCSSError::FunctionError { .. } => "FUNCTION ERROR",
+        RCSSError::ExpressionError { .. } => "EXPRESSION ERROR",
+        RCSSError::TraitError { .. } => "TRAIT ERROR",
+        RCSSError::SelectorError { .. } => "SELECTOR ERROR",
     };
 
     // Create the header
@@ -241,6 +302,18 @@ pub fn display_error(error: &RCSSError) {
         } => {
             display_error_with_context(file_path, *line, *column, message, context);
         }
+
+        RCSSError::ExpressionError { file_path, message, line, column, context } => {
+            display_error_with_context(file_path, *line, *column, message, context);
+        }
+
+        RCSSError::TraitError { file_path, trait_name: _, message, line, column, context } => {
+            display_error_with_context(file_path, *line, *column, message, context);
+        }
+
+        RCSSError::SelectorError { file_path, selector: _, message, line, column, context } => {
+            display_error_with_context(file_path, *line, *column, message, context);
+        }
     }
 
     println!("\n{}\n", "For help, open an issue on GitHub.".dimmed());
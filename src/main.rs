@@ -1,6 +1,8 @@
 // RCSS Project File Imports
 mod compile;
+mod dependency_graph;
 mod error;
+mod value;
 
 pub mod process_x {
     pub mod variables;
@@ -9,36 +11,131 @@ pub mod process_x {
     pub mod keyframes;
     pub mod imports;
     pub mod media_queries;
+    pub mod expressions;
+    pub mod builtins;
+    pub mod control_flow;
+    pub mod loops;
+    pub mod extends;
+    pub mod traits;
 }
 
-use process_x::{ variables, rule_normal, functions, keyframes, imports, media_queries };
+use process_x::{
+    variables,
+    rule_normal,
+    functions,
+    keyframes,
+    imports,
+    media_queries,
+    expressions,
+    builtins,
+    control_flow,
+    loops,
+    extends,
+    traits,
+};
 
 use error::Result;
 
 use clap::{ Arg, Command };
 use compile::compile;
+use dependency_graph::DependencyGraph;
 use std::path::Path;
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 
 use notify::event::{ AccessKind, AccessMode };
 use notify::{ recommended_watcher, Event, RecursiveMode, Watcher, EventKind };
 use std::sync::mpsc;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
 pub enum MetaData {
     Variables {
         name: String,
-        value: String,
+        value: value::Value,
+        public: bool,
     },
     Function {
         name: String,
-        body: Vec<String>,
+        parameters: Vec<functions::FunctionParameter>,
+        return_type: Option<String>,
+        body: String,
+        file_path: String,
+        public: bool,
+
+        // The items of the file the function was imported from, visible to its body.
+        // Shared between every item imported from that file rather than copied
+        scope: Rc<[MetaData]>,
     },
     Keyframes {
         name: String,
         body: HashMap<String, Vec<String>>,
     },
+    Trait {
+        name: String,
+        associated_variables: Vec<String>,
+        body: String,
+        file_path: String,
+        public: bool,
+        scope: Rc<[MetaData]>,
+    },
+}
+
+impl MetaData {
+    /// The name other files import the item by
+    pub fn name(&self) -> &str {
+        match self {
+            MetaData::Variables { name, .. } |
+            MetaData::Function { name, .. } |
+            MetaData::Keyframes { name, .. } |
+            MetaData::Trait { name, .. } => name,
+        }
+    }
+
+    /// Whether the item was declared with `pub`
+    pub fn is_public(&self) -> bool {
+        match self {
+            MetaData::Variables { public, .. } |
+            MetaData::Function { public, .. } |
+            MetaData::Trait { public, .. } => *public,
+            MetaData::Keyframes { .. } => false,
+        }
+    }
+
+    /// Prepares an item imported from a file with the given items: it is private
+    /// to the importer unless re-exported with `pub use`, and its body keeps
+    /// seeing the file it came from
+    pub fn imported(mut self, file_scope: &Rc<[MetaData]>, re_export: bool) -> Self {
+        match &mut self {
+            MetaData::Variables { public, .. } => {
+                *public = re_export;
+            }
+            MetaData::Function { public, scope, .. } | MetaData::Trait { public, scope, .. } => {
+                *public = re_export;
+
+                if scope.is_empty() {
+                    *scope = Rc::clone(file_scope);
+                }
+            }
+            MetaData::Keyframes { .. } => {}
+        }
+
+        self
+    }
+
+    /// Renames the item, as `use a::b as c;` does in the importing file
+    pub fn renamed(mut self, new_name: &str) -> Self {
+        match &mut self {
+            MetaData::Variables { name, .. } |
+            MetaData::Function { name, .. } |
+            MetaData::Keyframes { name, .. } |
+            MetaData::Trait { name, .. } => {
+                *name = new_name.to_string();
+            }
+        }
+
+        self
+    }
 }
 
 fn main() -> Result<()> {
@@ -56,6 +153,13 @@ fn main() -> Result<()> {
                 .help("Print verbose processing information")
                 .action(clap::ArgAction::SetTrue)
         )
+        .arg(
+            Arg::new("max-iterations")
+                .long("max-iterations")
+                .value_name("COUNT")
+                .help("Maximum number of iterations a single for loop may run (default: 1000)")
+                .value_parser(clap::value_parser!(usize))
+        )
         .get_matches();
 
     let input_path = Path::new(matches.get_one::<String>("folder").unwrap());
@@ -70,9 +174,15 @@ fn main() -> Result<()> {
     let css_input_path = css_input_path.canonicalize()?;
 
     let verbose = matches.get_flag("verbose");
+    let max_iterations = *matches
+        .get_one::<usize>("max-iterations")
+        .unwrap_or(&loops::DEFAULT_MAX_ITERATIONS);
 
     let mut project_meta_data: HashMap<String, Vec<MetaData>> = HashMap::new();
 
+    // Files whose errors were already shown, so their importers don't repeat them
+    let mut failed_files: HashSet<String> = HashSet::new();
+
     let mut rcss_files = Vec::new();
 
     fn collect_rcss_files(
@@ -98,18 +208,31 @@ fn main() -> Result<()> {
     collect_rcss_files(&rcss_input_path, &mut rcss_files, &rcss_input_path)?;
 
     let mut initial_compile_errors = 0;
+    let mut dependency_graph = DependencyGraph::default();
 
     for rcss_file in &rcss_files {
+        let rcss_path = rcss_input_path.join(rcss_file).to_str().unwrap().to_string();
+
+        dependency_graph.update(&rcss_path, rcss_input_path.to_str().unwrap());
+
+        // Already checked while compiling a file that imports it
+        if project_meta_data.contains_key(&rcss_path) || failed_files.contains(&rcss_path) {
+            continue;
+        }
+
         if
             let Err(_) = compile(
-                rcss_input_path.join(rcss_file).to_str().unwrap(),
+                &rcss_path,
                 css_input_path.join(rcss_file).with_extension("css").to_str().unwrap(),
                 rcss_input_path.to_str().unwrap(),
                 &mut project_meta_data,
+                &mut failed_files,
                 verbose,
-                true
+                true,
+                max_iterations
             )
         {
+            failed_files.insert(rcss_path);
             initial_compile_errors += 1;
         }
     }
@@ -149,14 +272,78 @@ fn main() -> Result<()> {
                             .join(rcss_file)
                             .with_extension("css");
 
-                        let _ = compile(
+                        dependency_graph.update(
                             rcss_combined_path.to_str().unwrap(),
-                            css_combined_path.to_str().unwrap(),
-                            rcss_input_path.to_str().unwrap(),
-                            &mut project_meta_data,
-                            verbose,
-                            false
+                            rcss_input_path.to_str().unwrap()
                         );
+
+                        // Saving may have fixed it, so its errors are worth showing again
+                        failed_files.remove(rcss_combined_path.to_str().unwrap());
+
+                        if
+                            compile(
+                                rcss_combined_path.to_str().unwrap(),
+                                css_combined_path.to_str().unwrap(),
+                                rcss_input_path.to_str().unwrap(),
+                                &mut project_meta_data,
+                                &mut failed_files,
+                                verbose,
+                                false,
+                                max_iterations
+                            ).is_err()
+                        {
+                            continue;
+                        }
+
+                        // Files using the saved one are rebuilt after whatever they import
+                        let dependents = dependency_graph.dependents(
+                            rcss_combined_path.to_str().unwrap()
+                        );
+
+                        let dependent_files: Vec<&Path> = dependents
+                            .iter()
+                            .filter_map(|dependent| {
+                                Path::new(dependent).strip_prefix(&rcss_input_path).ok()
+                            })
+                            .collect();
+
+                        if dependent_files.is_empty() {
+                            continue;
+                        }
+
+                        println!(
+                            "Rebuilding files that use {}: {}",
+                            rcss_file.display(),
+                            dependent_files
+                                .iter()
+                                .map(|file| file.display().to_string())
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        );
+
+                        for dependent_file in dependent_files {
+                            let _ = compile(
+                                rcss_input_path.join(dependent_file).to_str().unwrap(),
+                                css_input_path
+                                    .join(dependent_file)
+                                    .with_extension("css")
+                                    .to_str()
+                                    .unwrap(),
+                                rcss_input_path.to_str().unwrap(),
+                                &mut project_meta_data,
+                                &mut failed_files,
+                                verbose,
+                                false,
+                                max_iterations
+                            );
+                        }
+                    }
+                }
+
+                // Deleted files no longer need rebuilding
+                if let EventKind::Remove(_) = path.kind {
+                    for removed in &path.paths {
+                        dependency_graph.remove(removed.to_str().unwrap_or_default());
                     }
                 }
             }
@@ -0,0 +1,418 @@
+use crate::{ control_flow, value::{ format_number, ListSeparator, Value } };
+
+/// Functions provided by the compiler and evaluated at compile time
+const BUILTIN_FUNCTIONS: &[&str] = &[
+    "lighten",
+    "darken",
+    "mix",
+    "alpha",
+    "complement",
+    "grayscale",
+    "len",
+    "nth",
+    "join",
+    "append",
+    "index_of",
+];
+
+/// List built-ins that take a closure (ex: map(&sizes, |size| &size * 2)), evaluated
+/// by the expression evaluator since the closure needs the surrounding scope
+pub const CLOSURE_FUNCTIONS: &[&str] = &["map", "filter"];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_FUNCTIONS.contains(&name)
+}
+
+/// Whether a call to a built-in is really the CSS function of the same name, which
+/// takes an amount instead of a colour (ex: filter: grayscale(100%))
+pub fn is_css_function_call(name: &str, arguments: &[Value]) -> bool {
+    name == "grayscale" &&
+        arguments.iter().all(|argument| matches!(argument, Value::Number(_) | Value::Length(..)))
+}
+
+/// Evaluates a built-in function, returning a message describing the problem
+/// when the arguments are not what it expects
+pub fn call_builtin(name: &str, arguments: &[Value]) -> Result<Value, String> {
+    match name {
+        "lighten" | "darken" => {
+            let [color, amount] = expect_arguments(name, arguments, ["color", "amount"])?;
+            let color = Color::from_value(color)?;
+            let amount = fraction(amount)?;
+
+            let (hue, saturation, lightness) = color.to_hsl();
+            let lightness = if name == "lighten" { lightness + amount } else { lightness - amount };
+
+            Ok(Color::from_hsl(hue, saturation, lightness.clamp(0.0, 1.0), color.alpha).to_value())
+        }
+
+        "mix" => {
+            let (first, second, weight) = match arguments {
+                [first, second] => (first, second, 0.5),
+                [first, second, weight] => (first, second, fraction(weight)?),
+                _ => {
+                    return Err(
+                        format!("mix takes 2 or 3 arguments (color, color, weight) but {} were supplied", arguments.len())
+                    );
+                }
+            };
+
+            let first = Color::from_value(first)?;
+            let second = Color::from_value(second)?;
+            let weight = weight.clamp(0.0, 1.0);
+            let blend = |a: f64, b: f64| a * weight + b * (1.0 - weight);
+
+            Ok(
+                Color {
+                    red: blend(first.red, second.red),
+                    green: blend(first.green, second.green),
+                    blue: blend(first.blue, second.blue),
+                    alpha: blend(first.alpha, second.alpha),
+                }.to_value()
+            )
+        }
+
+        "alpha" => {
+            let [color, alpha] = expect_arguments(name, arguments, ["color", "alpha"])?;
+            let color = Color::from_value(color)?;
+
+            Ok(Color { alpha: fraction(alpha)?.clamp(0.0, 1.0), ..color }.to_value())
+        }
+
+        "complement" => {
+            let [color] = expect_arguments(name, arguments, ["color"])?;
+            let color = Color::from_value(color)?;
+            let (hue, saturation, lightness) = color.to_hsl();
+
+            Ok(Color::from_hsl((hue + 180.0) % 360.0, saturation, lightness, color.alpha).to_value())
+        }
+
+        "grayscale" => {
+            let [color] = expect_arguments(name, arguments, ["color"])?;
+            let color = Color::from_value(color)?;
+            let (hue, _, lightness) = color.to_hsl();
+
+            Ok(Color::from_hsl(hue, 0.0, lightness, color.alpha).to_value())
+        }
+
+        "len" => {
+            let [list] = expect_arguments(name, arguments, ["list"])?;
+
+            let length = match list {
+                Value::Map(entries) => entries.len(),
+                list => list_items(list).0.len(),
+            };
+
+            Ok(Value::Number(length as f64))
+        }
+
+        // Indexes start at 0, like ranges (ex: for i in 0..len(&sizes))
+        "nth" => {
+            let [list, index] = expect_arguments(name, arguments, ["list", "index"])?;
+            let (items, _) = list_items(list);
+
+            let index = match index {
+                Value::Number(number) if number.fract() == 0.0 && *number >= 0.0 => *number as usize,
+                index => {
+                    return Err(
+                        format!("Expected a whole number index but found {} ({})", index.type_name(), index)
+                    );
+                }
+            };
+
+            items
+                .get(index)
+                .cloned()
+                .ok_or_else(|| {
+                    format!("Index {} is out of bounds for a list of length {}", index, items.len())
+                })
+        }
+
+        "join" => {
+            let [first, second] = expect_arguments(name, arguments, ["list", "list"])?;
+            let (mut items, separator) = list_items(first);
+            let (other_items, other_separator) = list_items(second);
+
+            let separator = match first {
+                Value::List(..) => separator,
+                _ => other_separator,
+            };
+
+            items.extend(other_items);
+
+            Ok(Value::List(items, separator))
+        }
+
+        "append" => {
+            let [list, value] = expect_arguments(name, arguments, ["list", "value"])?;
+            let (mut items, separator) = list_items(list);
+
+            items.push(value.clone());
+
+            Ok(Value::List(items, separator))
+        }
+
+        "index_of" => {
+            let [list, value] = expect_arguments(name, arguments, ["list", "value"])?;
+            let (items, _) = list_items(list);
+
+            Ok(
+                match items.iter().position(|item| control_flow::values_equal(item, value)) {
+                    Some(index) => Value::Number(index as f64),
+                    None => Value::Keyword("none".to_string()),
+                }
+            )
+        }
+
+        _ => Err(format!("Unknown built-in function: {}", name)),
+    }
+}
+
+fn expect_arguments<'a, const N: usize>(
+    name: &str,
+    arguments: &'a [Value],
+    expected: [&str; N]
+) -> Result<&'a [Value; N], String> {
+    arguments
+        .try_into()
+        .map_err(|_| {
+            format!(
+                "{} takes {} argument(s) ({}) but {} were supplied",
+                name,
+                N,
+                expected.join(", "),
+                arguments.len()
+            )
+        })
+}
+
+/// Returns the items of a list, treating any other value as a list of one
+pub fn list_items(value: &Value) -> (Vec<Value>, ListSeparator) {
+    match value {
+        Value::List(items, separator) => (items.clone(), *separator),
+        value => (vec![value.clone()], ListSeparator::Space),
+    }
+}
+
+/// Reads an amount written as a percentage (ex: 10%) or a number between 0 and 1
+fn fraction(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Length(number, unit) if unit == "%" => Ok(number / 100.0),
+        Value::Number(number) => Ok(*number),
+        value => Err(format!("Expected a percentage or a number but found {} ({})", value.type_name(), value)),
+    }
+}
+
+/// An sRGB colour with channels between 0 and 255 and an alpha between 0 and 1
+#[derive(Debug, Clone, Copy)]
+struct Color {
+    red: f64,
+    green: f64,
+    blue: f64,
+    alpha: f64,
+}
+
+impl Color {
+    fn from_value(value: &Value) -> Result<Color, String> {
+        let text = match value {
+            Value::Color(text) | Value::Raw(text) | Value::String(text) => text.trim(),
+            value => {
+                return Err(
+                    format!("Expected a hex, rgb() or hsl() colour but found {} ({})", value.type_name(), value)
+                );
+            }
+        };
+
+        let color = if let Some(hex) = text.strip_prefix('#') {
+            Color::from_hex(hex)
+        } else {
+            Color::from_function(text)
+        };
+
+        color.ok_or_else(|| format!("Unknown colour: {} (expected a hex, rgb() or hsl() colour)", text))
+    }
+
+    fn from_hex(hex: &str) -> Option<Color> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        // Short forms (ex: #f0a) repeat every digit
+        let digits: String = match hex.len() {
+            3 | 4 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => hex.to_string(),
+            _ => {
+                return None;
+            }
+        };
+
+        let channel = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16).ok().map(f64::from);
+
+        Some(Color {
+            red: channel(0)?,
+            green: channel(2)?,
+            blue: channel(4)?,
+            alpha: if digits.len() == 8 { channel(6)? / 255.0 } else { 1.0 },
+        })
+    }
+
+    /// Parses rgb(), rgba(), hsl() and hsla() with comma or space separated arguments
+    fn from_function(text: &str) -> Option<Color> {
+        let (name, arguments) = text.split_once('(')?;
+        let arguments = arguments.strip_suffix(')')?;
+
+        let components: Vec<&str> = arguments
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|component| !component.is_empty())
+            .collect();
+
+        if components.len() < 3 || components.len() > 4 {
+            return None;
+        }
+
+        let alpha = match components.get(3) {
+            Some(alpha) => parse_component(alpha, 1.0)?,
+            None => 1.0,
+        };
+
+        match name.trim() {
+            "rgb" | "rgba" =>
+                Some(Color {
+                    red: parse_component(components[0], 255.0)?,
+                    green: parse_component(components[1], 255.0)?,
+                    blue: parse_component(components[2], 255.0)?,
+                    alpha,
+                }),
+
+            "hsl" | "hsla" => {
+                let hue = components[0].trim_end_matches("deg").parse::<f64>().ok()?;
+                let saturation = parse_component(components[1], 1.0)?;
+                let lightness = parse_component(components[2], 1.0)?;
+
+                Some(Color::from_hsl(hue.rem_euclid(360.0), saturation, lightness, alpha))
+            }
+
+            _ => None,
+        }
+    }
+
+    fn from_hsl(hue: f64, saturation: f64, lightness: f64, alpha: f64) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let x = chroma * (1.0 - (((hue / 60.0) % 2.0) - 1.0).abs());
+        let m = lightness - chroma / 2.0;
+
+        let (red, green, blue) = match hue {
+            h if h < 60.0 => (chroma, x, 0.0),
+            h if h < 120.0 => (x, chroma, 0.0),
+            h if h < 180.0 => (0.0, chroma, x),
+            h if h < 240.0 => (0.0, x, chroma),
+            h if h < 300.0 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        Color {
+            red: (red + m) * 255.0,
+            green: (green + m) * 255.0,
+            blue: (blue + m) * 255.0,
+            alpha,
+        }
+    }
+
+    /// Returns the hue in degrees and the saturation and lightness between 0 and 1
+    fn to_hsl(self) -> (f64, f64, f64) {
+        let red = self.red / 255.0;
+        let green = self.green / 255.0;
+        let blue = self.blue / 255.0;
+
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let delta = max - min;
+        let lightness = (max + min) / 2.0;
+
+        if delta == 0.0 {
+            return (0.0, 0.0, lightness);
+        }
+
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+
+        let hue = if max == red {
+            60.0 * ((green - blue) / delta).rem_euclid(6.0)
+        } else if max == green {
+            60.0 * ((blue - red) / delta + 2.0)
+        } else {
+            60.0 * ((red - green) / delta + 4.0)
+        };
+
+        (hue, saturation, lightness)
+    }
+
+    /// Opaque colours are written as hex, translucent ones as rgba()
+    fn to_value(self) -> Value {
+        let channel = |value: f64| value.round().clamp(0.0, 255.0) as u8;
+        let (red, green, blue) = (channel(self.red), channel(self.green), channel(self.blue));
+
+        if self.alpha >= 1.0 {
+            Value::Color(format!("#{:02x}{:02x}{:02x}", red, green, blue))
+        } else {
+            Value::Color(
+                format!("rgba({}, {}, {}, {})", red, green, blue, format_number(self.alpha.max(0.0)))
+            )
+        }
+    }
+}
+
+/// Reads a colour component written as a number or a percentage of `scale`
+fn parse_component(component: &str, scale: f64) -> Option<f64> {
+    match component.strip_suffix('%') {
+        Some(percentage) => Some((percentage.parse::<f64>().ok()? / 100.0) * scale),
+        None if scale == 1.0 && component.parse::<f64>().ok()? > 1.0 => None,
+        None => component.parse::<f64>().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        let color = Color::from_hex("f0a").unwrap();
+        assert_eq!((color.red, color.green, color.blue, color.alpha), (255.0, 0.0, 170.0, 1.0));
+
+        let color = Color::from_hex("33669980").unwrap();
+        assert_eq!((color.red, color.green, color.blue), (51.0, 102.0, 153.0));
+        assert!((color.alpha - 128.0 / 255.0).abs() < 1e-9);
+
+        assert!(Color::from_hex("12345").is_none());
+        assert!(Color::from_hex("ggg").is_none());
+    }
+
+    #[test]
+    fn converts_to_hsl() {
+        let (hue, saturation, lightness) = Color::from_hex("336699").unwrap().to_hsl();
+
+        assert!((hue - 210.0).abs() < 1e-9);
+        assert!((saturation - 0.5).abs() < 1e-9);
+        assert!((lightness - 0.4).abs() < 1e-9);
+
+        assert_eq!(Color::from_hex("808080").unwrap().to_hsl().1, 0.0);
+    }
+
+    #[test]
+    fn round_trips_through_hsl() {
+        for hex in ["336699", "ff0000", "00ff00", "0000ff", "ffffff", "000000", "c0ffee", "7f3f9a"] {
+            let (hue, saturation, lightness) = Color::from_hex(hex).unwrap().to_hsl();
+            let value = Color::from_hsl(hue, saturation, lightness, 1.0).to_value();
+
+            assert_eq!(value, Value::Color(format!("#{}", hex)));
+        }
+    }
+
+    #[test]
+    fn grayscale_with_an_amount_is_the_css_function() {
+        assert!(is_css_function_call("grayscale", &[Value::Length(100.0, "%".to_string())]));
+        assert!(is_css_function_call("grayscale", &[Value::Number(0.5)]));
+        assert!(is_css_function_call("grayscale", &[]));
+        assert!(!is_css_function_call("grayscale", &[Value::Color("#336699".to_string())]));
+        assert!(!is_css_function_call("lighten", &[Value::Number(0.5)]));
+    }
+}
@@ -0,0 +1,248 @@
+use pest::iterators::Pair;
+use std::cmp::Ordering;
+use crate::{
+    compile::Rule,
+    error::{ display_error, RCSSError, get_error_context },
+    expressions,
+    value::Value,
+    MetaData,
+    Result,
+};
+
+/// Evaluates an `if` or `match` statement and returns the block of the branch
+/// that was taken, if any
+pub fn select_branch<'a>(
+    pair: Pair<'a, Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Option<Pair<'a, Rule>>> {
+    match pair.as_rule() {
+        Rule::if_statement | Rule::top_if_statement => {
+            let mut inner_pairs = pair.into_inner();
+
+            let (Some(condition), Some(block)) = (inner_pairs.next(), inner_pairs.next()) else {
+                return Ok(None);
+            };
+
+            if evaluate_condition(condition, meta_data, raw_rcss, input_path)? {
+                return Ok(Some(block));
+            }
+
+            match inner_pairs.next() {
+                Some(else_if) if matches!(
+                    else_if.as_rule(),
+                    Rule::if_statement | Rule::top_if_statement
+                ) => select_branch(else_if, meta_data, raw_rcss, input_path),
+                else_block => Ok(else_block),
+            }
+        }
+
+        Rule::match_statement | Rule::top_match_statement => {
+            let mut inner_pairs = pair.into_inner();
+
+            let Some(subject) = inner_pairs.next() else {
+                return Ok(None);
+            };
+
+            let subject = expressions::evaluate_value(subject, meta_data, raw_rcss, input_path)?;
+
+            for arm in inner_pairs {
+                let mut arm_pairs = arm.into_inner();
+
+                let (Some(pattern), Some(block)) = (arm_pairs.next(), arm_pairs.next()) else {
+                    continue;
+                };
+
+                for alternative in pattern.into_inner() {
+                    let matched = match alternative.as_rule() {
+                        Rule::match_wildcard => true,
+                        _ => {
+                            let value = expressions::evaluate_value(
+                                alternative,
+                                meta_data,
+                                raw_rcss,
+                                input_path
+                            )?;
+
+                            values_equal(&subject, &value)
+                        }
+                    };
+
+                    if matched {
+                        return Ok(Some(block));
+                    }
+                }
+            }
+
+            Ok(None)
+        }
+
+        _ => Ok(None),
+    }
+}
+
+/// Evaluates a condition that may also be a plain value (ex: the body of
+/// `|size| &size * 2`), giving `true` or `false` when it compares values
+pub fn evaluate_expression(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Value> {
+    // condition -> condition_and -> condition_not -> comparison -> condition_value
+    let mut lone_value = Some(pair.clone());
+
+    for _ in 0..4 {
+        lone_value = lone_value.and_then(|p| {
+            let mut inner_pairs = p.into_inner();
+
+            match (inner_pairs.next(), inner_pairs.next()) {
+                (Some(inner), None) if inner.as_rule() != Rule::negation => Some(inner),
+                _ => None,
+            }
+        });
+    }
+
+    match lone_value {
+        Some(value) if value.as_rule() == Rule::condition_value => {
+            expressions::evaluate_value(value, meta_data, raw_rcss, input_path)
+        }
+        _ => {
+            let result = evaluate_condition(pair, meta_data, raw_rcss, input_path)?;
+            Ok(Value::Keyword(result.to_string()))
+        }
+    }
+}
+
+fn evaluate_condition(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<bool> {
+    match pair.as_rule() {
+        Rule::condition => {
+            for in_pair in pair.into_inner() {
+                if evaluate_condition(in_pair, meta_data, raw_rcss, input_path)? {
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        }
+
+        Rule::condition_and => {
+            for in_pair in pair.into_inner() {
+                if !evaluate_condition(in_pair, meta_data, raw_rcss, input_path)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+
+        Rule::condition_not => {
+            let mut negated = false;
+            let mut result = false;
+
+            for in_pair in pair.into_inner() {
+                match in_pair.as_rule() {
+                    Rule::negation => {
+                        negated = !negated;
+                    }
+                    _ => {
+                        result = evaluate_condition(in_pair, meta_data, raw_rcss, input_path)?;
+                    }
+                }
+            }
+
+            Ok(result != negated)
+        }
+
+        Rule::comparison => {
+            let mut inner_pairs = pair.into_inner();
+
+            let Some(left) = inner_pairs.next() else {
+                return Ok(false);
+            };
+
+            let left = expressions::evaluate_value(left, meta_data, raw_rcss, input_path)?;
+
+            let (Some(operator), Some(right)) = (inner_pairs.next(), inner_pairs.next()) else {
+                return Ok(is_truthy(&left));
+            };
+
+            let right = expressions::evaluate_value(right, meta_data, raw_rcss, input_path)?;
+
+            let ordering = match operator.as_str() {
+                "==" => {
+                    return Ok(values_equal(&left, &right));
+                }
+                "!=" => {
+                    return Ok(!values_equal(&left, &right));
+                }
+                _ => expressions::compare_values(&left, &right),
+            };
+
+            let Some(ordering) = ordering else {
+                let (line, column) = operator.line_col();
+                let context = get_error_context(raw_rcss, line, 2);
+
+                let err = RCSSError::ExpressionError {
+                    file_path: input_path.into(),
+                    line,
+                    column,
+                    message: format!(
+                        "Cannot compare {} ({}) and {} ({}) with '{}'",
+                        left.type_name(),
+                        left,
+                        right.type_name(),
+                        right,
+                        operator.as_str()
+                    ),
+                    context,
+                };
+
+                display_error(&err);
+                return Err(err);
+            };
+
+            Ok(match operator.as_str() {
+                "<" => ordering == Ordering::Less,
+                "<=" => ordering != Ordering::Greater,
+                ">" => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            })
+        }
+
+        _ => Ok(false),
+    }
+}
+
+/// Numbers are compared by value (ex: 1in == 96px), anything else by its text,
+/// so `"dark"`, `dark` and `let theme: "dark";` are all equal
+pub fn values_equal(left: &Value, right: &Value) -> bool {
+    if let Some(ordering) = expressions::compare_values(left, right) {
+        return ordering == Ordering::Equal;
+    }
+
+    comparable_text(left) == comparable_text(right)
+}
+
+fn comparable_text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// A value on its own is true unless it is `false`, zero or an empty string
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Number(number) => *number != 0.0,
+        Value::String(string) | Value::Raw(string) => !string.is_empty() && string != "false",
+        Value::Keyword(keyword) => keyword != "false",
+        _ => true,
+    }
+}
@@ -0,0 +1,1361 @@
+use pest::{ iterators::Pair, Parser };
+use std::{ cmp::Ordering, f64::consts::PI };
+use crate::{
+    compile::{ Rule, RCSSParser },
+    error::{ display_error, RCSSError, get_error_context },
+    builtins,
+    control_flow,
+    functions,
+    value::{ ListSeparator, Value },
+    variables,
+    MetaData,
+    Result,
+};
+
+/// CSS functions whose arguments are left for the browser to evaluate
+const CSS_MATH_FUNCTIONS: &[&str] = &["calc", "min", "max", "clamp"];
+
+/// Units that can be converted into each other, relative to the first unit of each group
+const UNIT_GROUPS: &[&[(&str, f64)]] = &[
+    &[
+        ("px", 1.0),
+        ("in", 96.0),
+        ("cm", 96.0 / 2.54),
+        ("mm", 96.0 / 25.4),
+        ("pt", 96.0 / 72.0),
+        ("pc", 16.0),
+    ],
+    &[
+        ("ms", 1.0),
+        ("s", 1000.0),
+    ],
+    &[
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / PI),
+        ("turn", 360.0),
+    ],
+];
+
+/// Where a value is being evaluated, passed down through nested calls
+struct Scope<'a> {
+    meta_data: &'a [MetaData],
+    bare_names: &'a [String],
+    raw_rcss: &'a str,
+    input_path: &'a str,
+}
+
+/// A piece of a value: an operand, an operator or the comma between list items
+enum Token {
+    Operand {
+        value: Value,
+        text: String,
+        computed: bool,
+    },
+    Operator(char),
+    Comma(String),
+}
+
+struct Item {
+    token: Token,
+    gap: String,
+    line_col: (usize, usize),
+}
+
+/// The result of evaluating part of a value
+struct Evaluated {
+    value: Value,
+    text: String,
+    computed: bool,
+    gap: String,
+
+    // The operator that produced it, if any (ex: '/' for a kept 12px/1.5)
+    operator: Option<char>,
+}
+
+/// Returns the text of `pair` with its variable references, user created
+/// value functions and arithmetic resolved. Any other function call (ex: blur())
+/// is passed through to the CSS output.
+pub fn resolve_value(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<String> {
+    let scope = Scope { meta_data, bare_names: &[], raw_rcss, input_path };
+    let mut call_stack = Vec::new();
+    let (_, text) = evaluate_container(pair, &scope, &mut call_stack)?;
+
+    Ok(text.trim().to_string())
+}
+
+/// Like resolve_value, for a declaration of the CSS output where a whole map
+/// can't go (ex: color: &theme; instead of color: &theme.primary;)
+pub fn resolve_declaration(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<String> {
+    let scope = Scope { meta_data, bare_names: &[], raw_rcss, input_path };
+    let mut call_stack = Vec::new();
+    let (value, text) = evaluate_container(pair.clone(), &scope, &mut call_stack)?;
+
+    let Some(entries) = find_map(&value) else {
+        return Ok(text.trim().to_string());
+    };
+
+    // Points at the variable holding the map when the declaration names one
+    let reference = pair
+        .clone()
+        .into_inner()
+        .filter(|child| child.as_rule() == Rule::variable_reference)
+        .map(|child| (child.line_col(), child.as_str().trim_start_matches('&').to_string(), child))
+        .find(|(_, name, child)| matches!(find_variable(child, name, &scope), Ok(Value::Map(_))))
+        .map(|(line_col, name, _)| (line_col, name));
+
+    // Otherwise at the value as written (ex: a function returning a map)
+    let ((line, column), name) = reference.unwrap_or_else(|| {
+        let children: Vec<Pair<Rule>> = pair.clone().into_inner().filter(is_value_token).collect();
+
+        match (children.first(), children.last()) {
+            (Some(first), Some(last)) => {
+                let start = pair.as_span().start();
+                let source = &pair.as_str()[first.as_span().start() - start..last.as_span().end() - start];
+                (first.line_col(), source.to_string())
+            }
+            _ => (pair.line_col(), text.trim().to_string()),
+        }
+    });
+    let context = get_error_context(raw_rcss, line, 2);
+
+    let fields: Vec<&str> = entries
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect();
+
+    let err = RCSSError::VariableError {
+        file_path: input_path.into(),
+        line,
+        column,
+        variable_name: name.clone(),
+        message: format!(
+            "Map {} can't be used as a value, use one of its fields (available fields: {})",
+            name,
+            fields.join(", ")
+        ),
+        context,
+    };
+
+    display_error(&err);
+    Err(err)
+}
+
+/// The entries of the first map in a value, looking inside lists
+fn find_map(value: &Value) -> Option<&Vec<(String, Value)>> {
+    match value {
+        Value::Map(entries) => Some(entries),
+        Value::List(values, _) => values.iter().find_map(find_map),
+        _ => None,
+    }
+}
+
+/// Evaluates `pair` into a typed value
+pub fn evaluate_value(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Value> {
+    let scope = Scope { meta_data, bare_names: &[], raw_rcss, input_path };
+    let mut call_stack = Vec::new();
+    let (value, _) = evaluate_container(pair, &scope, &mut call_stack)?;
+
+    Ok(value)
+}
+
+/// Returns the text of `pair` with every `{name}` or `{&name}` replaced by the
+/// value of that variable (ex: .col-{i} -> .col-3)
+pub fn interpolate(
+    pair: &Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<String> {
+    let scope = Scope { meta_data, bare_names: &[], raw_rcss, input_path };
+
+    interpolate_text(pair, pair.as_str().len(), &scope)
+}
+
+/// Interpolates the first `length` bytes of the text of `pair`
+fn interpolate_text(pair: &Pair<Rule>, length: usize, scope: &Scope) -> Result<String> {
+    let text = pair.as_str();
+    let start = pair.as_span().start();
+    let mut result = String::new();
+    let mut last = 0;
+
+    let interpolations = pair
+        .clone()
+        .into_inner()
+        .flatten()
+        .filter(|p| p.as_rule() == Rule::interpolation && p.as_span().end() - start <= length);
+
+    for interpolation in interpolations {
+        let name = interpolation.as_str().trim_matches(|c| c == '{' || c == '}' || c == '&');
+
+        result.push_str(&text[last..interpolation.as_span().start() - start]);
+        last = interpolation.as_span().end() - start;
+
+        match find_variable(&interpolation, name, scope)? {
+            Value::String(string) => result.push_str(&string),
+            value => result.push_str(&value.to_string()),
+        }
+    }
+
+    result.push_str(&text[last..length]);
+
+    Ok(result)
+}
+
+fn is_value_token(pair: &Pair<Rule>) -> bool {
+    matches!(
+        pair.as_rule(),
+        Rule::function_call |
+            Rule::del_val_keyword |
+            Rule::del_val_color |
+            Rule::del_val_length |
+            Rule::variable_reference |
+            Rule::string_literal |
+            Rule::list_literal |
+            Rule::value_group |
+            Rule::arithmetic_operator |
+            Rule::css_operator |
+            Rule::important
+    )
+}
+
+/// Evaluates the value inside a pair such as a declaration or an argument,
+/// keeping the text before (ex: "color: ") and after (ex: ";") it as written
+fn evaluate_container(
+    pair: Pair<Rule>,
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<(Value, String)> {
+    if pair.as_rule() == Rule::map_literal {
+        let value = evaluate_map(pair, scope, call_stack)?;
+        return Ok((value.clone(), value.to_string()));
+    }
+
+    let text = pair.as_str();
+    let start = pair.as_span().start();
+    let children: Vec<Pair<Rule>> = pair.clone().into_inner().filter(is_value_token).collect();
+
+    let (Some(first), Some(last)) = (children.first(), children.last()) else {
+        let text = interpolate_text(&pair, text.len(), scope)?;
+        return Ok((Value::Raw(text.trim().to_string()), text));
+    };
+
+    // The property name of a declaration may be interpolated (ex: margin-{&side}: 0;)
+    let prefix = interpolate_text(&pair, first.as_span().start() - start, scope)?;
+    let suffix = &text[last.as_span().end() - start..];
+
+    let items = tokenize(&pair, children, scope, call_stack)?;
+
+    // Like Sass, `/` only divides where it can't be a CSS separator (ex: font: 12px/1.5)
+    let divide = matches!(pair.as_rule(), Rule::value_group | Rule::return_expression);
+    let groups = ExpressionParser { items: &items, position: 0, scope, divide }.parse()?;
+
+    let mut value_text = String::new();
+    let mut values = Vec::new();
+
+    for (separator, group) in groups {
+        value_text.push_str(&separator);
+
+        let mut group_values = Vec::new();
+
+        for evaluated in group {
+            value_text.push_str(&evaluated.gap);
+            value_text.push_str(&evaluated.text);
+            group_values.push(evaluated.value);
+        }
+
+        values.push(
+            if group_values.len() == 1 {
+                group_values.remove(0)
+            } else {
+                Value::List(group_values, ListSeparator::Space)
+            }
+        );
+    }
+
+    let value = if values.len() == 1 {
+        values.remove(0)
+    } else {
+        Value::List(values, ListSeparator::Comma)
+    };
+
+    Ok((value, format!("{}{}{}", prefix, value_text, suffix)))
+}
+
+/// Turns the value children of `parent` into operands, operators and commas
+fn tokenize(
+    parent: &Pair<Rule>,
+    children: Vec<Pair<Rule>>,
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<Vec<Item>> {
+    let text = parent.as_str();
+    let start = parent.as_span().start();
+    let mut items = Vec::new();
+    let mut last: Option<usize> = None;
+
+    for child in children {
+        let child_start = child.as_span().start() - start;
+        let gap = last.map_or("", |last| &text[last..child_start]).to_string();
+        let line_col = child.line_col();
+        last = Some(child.as_span().end() - start);
+
+        // Commas written directly in the grammar only show up in the gaps
+        let gap = match gap.find(',') {
+            Some(_) if child.as_rule() != Rule::css_operator => {
+                items.push(Item { token: Token::Comma(gap), gap: String::new(), line_col });
+                String::new()
+            }
+            _ => gap,
+        };
+
+        let token = match child.as_rule() {
+            Rule::arithmetic_operator => {
+                Token::Operator(child.as_str().chars().next().unwrap_or_default())
+            }
+
+            Rule::css_operator => Token::Comma(format!("{}{}", gap, child.as_str())),
+
+            Rule::variable_reference => {
+                let value = find_variable(&child, child.as_str().trim_start_matches('&'), scope)?;
+                Token::Operand { text: value.to_string(), value, computed: true }
+            }
+
+            // Parameters can be referenced without `&` inside a return expression
+            Rule::del_val_keyword if scope.bare_names.iter().any(|name| name == child.as_str()) => {
+                let value = find_variable(&child, child.as_str(), scope)?;
+                Token::Operand { text: value.to_string(), value, computed: true }
+            }
+
+            Rule::function_call => evaluate_function_call(child, scope, call_stack)?,
+
+            Rule::value_group => {
+                let (value, text) = evaluate_container(child, scope, call_stack)?;
+
+                match value {
+                    Value::List(..) | Value::Raw(_) => Token::Operand { value, text, computed: true },
+                    value => Token::Operand { text: value.to_string(), value, computed: true },
+                }
+            }
+
+            Rule::list_literal => {
+                let mut values = Vec::new();
+
+                for list_item in child.into_inner() {
+                    values.push(evaluate_container(list_item, scope, call_stack)?.0);
+                }
+
+                let value = Value::List(values, ListSeparator::Comma);
+                Token::Operand { text: value.to_string(), value, computed: true }
+            }
+
+            Rule::important => {
+                Token::Operand {
+                    value: Value::Raw(child.as_str().to_string()),
+                    text: child.as_str().to_string(),
+                    computed: false,
+                }
+            }
+
+            _ => {
+                Token::Operand {
+                    text: child.as_str().to_string(),
+                    value: Value::from_pair(child),
+                    computed: false,
+                }
+            }
+        };
+
+        match token {
+            Token::Comma(_) => items.push(Item { token, gap: String::new(), line_col }),
+            token => items.push(Item { token, gap, line_col }),
+        }
+    }
+
+    Ok(items)
+}
+
+/// Finds a variable, following any map fields after it (ex: theme.colors.primary)
+fn find_variable(pair: &Pair<Rule>, variable_name: &str, scope: &Scope) -> Result<Value> {
+    let variable_error = |name: &str, message: String| {
+        let position = pair.line_col();
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(scope.raw_rcss, line, 2);
+
+        let err = RCSSError::VariableError {
+            file_path: scope.input_path.into(),
+            line,
+            column,
+            variable_name: name.to_string(),
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let mut path = variable_name.split('.');
+    let name = path.next().unwrap_or_default();
+
+    let Some(mut value) = variables::find_variable(scope.meta_data, name) else {
+        return Err(variable_error(name, format!("Could not find variable: {}", name)));
+    };
+
+    let mut walked = name.to_string();
+
+    for field in path {
+        let Value::Map(entries) = value else {
+            return Err(
+                variable_error(
+                    name,
+                    format!("{} is a {} ({}), not a map", walked, value.type_name(), value)
+                )
+            );
+        };
+
+        let Some((_, field_value)) = entries.iter().find(|(key, _)| key == field) else {
+            let fields: Vec<&str> = entries
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect();
+
+            return Err(
+                variable_error(
+                    name,
+                    format!(
+                        "Map {} has no field {} (available fields: {})",
+                        walked,
+                        field,
+                        fields.join(", ")
+                    )
+                )
+            );
+        };
+
+        value = field_value;
+        walked = format!("{}.{}", walked, field);
+    }
+
+    Ok(value.clone())
+}
+
+/// Evaluates a map literal, resolving the variables and functions in its values
+fn evaluate_map(pair: Pair<Rule>, scope: &Scope, call_stack: &mut Vec<String>) -> Result<Value> {
+    let mut entries = Vec::new();
+
+    for entry in pair.into_inner() {
+        let mut entry_pairs = entry.into_inner();
+
+        let (Some(key), Some(map_value)) = (entry_pairs.next(), entry_pairs.next()) else {
+            continue;
+        };
+
+        let value = match map_value.clone().into_inner().next() {
+            Some(inner) if inner.as_rule() == Rule::map_literal => {
+                evaluate_map(inner, scope, call_stack)?
+            }
+            _ => evaluate_container(map_value, scope, call_stack)?.0,
+        };
+
+        entries.push((key.as_str().to_string(), value));
+    }
+
+    Ok(Value::Map(entries))
+}
+
+fn evaluate_function_call(
+    pair: Pair<Rule>,
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<Token> {
+    let func_name = pair.as_str().split('(').next().unwrap_or_default().to_string();
+
+    let function = scope.meta_data
+        .iter()
+        .rev()
+        .find_map(|data| {
+            match data {
+                MetaData::Function {
+                    name,
+                    parameters,
+                    return_type,
+                    body,
+                    file_path,
+                    scope: captured,
+                    ..
+                } if *name == func_name => Some((parameters, return_type, body, file_path, captured)),
+                _ => None,
+            }
+        });
+
+    // A function without `-> type` only expands declarations, so it can't be a value
+    if let Some((_, None, ..)) = function {
+        let (line, column) = pair.line_col();
+        let context = get_error_context(scope.raw_rcss, line, 2);
+
+        let err = RCSSError::FunctionError {
+            file_path: scope.input_path.into(),
+            line,
+            column,
+            function_name: func_name.clone(),
+            message: format!("{} does not return a value", func_name),
+            context,
+        };
+
+        display_error(&err);
+        return Err(err);
+    }
+
+    if let Some((parameters, Some(return_type), body, file_path, captured)) = function {
+        let (value, text) = call_value_function(
+            pair,
+            &func_name,
+            parameters,
+            return_type,
+            body,
+            file_path,
+            captured,
+            scope,
+            call_stack
+        )?;
+
+        return Ok(Token::Operand { value, text, computed: true });
+    }
+
+    let closure = pair
+        .clone()
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::closure);
+
+    // Without a closure map() and filter() are left alone, filter() is also a CSS function
+    if let Some(closure) = closure && builtins::CLOSURE_FUNCTIONS.contains(&func_name.as_str()) {
+        let value = call_closure_function(pair, &func_name, closure, scope, call_stack)?;
+        return Ok(Token::Operand { text: value.to_string(), value, computed: true });
+    }
+
+    if builtins::is_builtin(&func_name) {
+        let position = pair.line_col();
+        let arguments = evaluate_arguments(pair.clone(), scope, call_stack)?;
+
+        // ex: filter: grayscale(100%) is the CSS filter function, passed through below
+        if !builtins::is_css_function_call(&func_name, &arguments) {
+            return match builtins::call_builtin(&func_name, &arguments) {
+                Ok(value) => Ok(Token::Operand { text: value.to_string(), value, computed: true }),
+                Err(message) => {
+                    let line = position.0;
+                    let column = position.1;
+                    let context = get_error_context(scope.raw_rcss, line, 2);
+
+                    let err = RCSSError::FunctionError {
+                        file_path: scope.input_path.into(),
+                        line,
+                        column,
+                        function_name: func_name,
+                        message,
+                        context,
+                    };
+
+                    display_error(&err);
+                    Err(err)
+                }
+            };
+        }
+    }
+
+    // Math functions are left to the browser, only their variables are resolved
+    let text = if CSS_MATH_FUNCTIONS.contains(&func_name.as_str()) {
+        rebuild_pair(pair, scope, call_stack)?
+    } else {
+        evaluate_container(pair, scope, call_stack)?.1
+    };
+
+    Ok(Token::Operand { value: Value::Raw(text.clone()), text, computed: false })
+}
+
+/// Evaluates map() or filter(), running the closure once for every item of the list
+fn call_closure_function(
+    pair: Pair<Rule>,
+    func_name: &str,
+    closure: Pair<Rule>,
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<Value> {
+    let position = pair.line_col();
+    let arguments = evaluate_arguments(pair, scope, call_stack)?;
+
+    let [list] = arguments.as_slice() else {
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(scope.raw_rcss, line, 2);
+
+        let err = RCSSError::FunctionError {
+            file_path: scope.input_path.into(),
+            line,
+            column,
+            function_name: func_name.to_string(),
+            message: format!(
+                "{} takes 2 arguments (list, closure) but {} were supplied",
+                func_name,
+                arguments.len() + 1
+            ),
+            context,
+        };
+
+        display_error(&err);
+        return Err(err);
+    };
+
+    let mut closure_pairs = closure.into_inner();
+
+    let (Some(parameter), Some(body)) = (closure_pairs.next(), closure_pairs.next()) else {
+        return Ok(list.clone());
+    };
+
+    let (items, separator) = builtins::list_items(list);
+    let mut results = Vec::new();
+
+    for item in items {
+        // The parameter shadows globals inside the closure body only
+        let mut meta_data = scope.meta_data.to_vec();
+        meta_data.push(MetaData::Variables {
+            name: parameter.as_str().to_string(),
+            value: item.clone(),
+            public: false,
+        });
+
+        let result = control_flow::evaluate_expression(
+            body.clone(),
+            &meta_data,
+            scope.raw_rcss,
+            scope.input_path
+        )?;
+
+        match func_name {
+            "filter" if control_flow::is_truthy(&result) => results.push(item),
+            "filter" => {}
+            _ => results.push(result),
+        }
+    }
+
+    Ok(Value::List(results, separator))
+}
+
+/// Evaluates every comma separated argument of a function call
+fn evaluate_arguments(
+    pair: Pair<Rule>,
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<Vec<Value>> {
+    let children: Vec<Pair<Rule>> = pair.clone().into_inner().filter(is_value_token).collect();
+    let items = tokenize(&pair, children, scope, call_stack)?;
+    let groups = ExpressionParser { items: &items, position: 0, scope, divide: false }.parse()?;
+
+    Ok(
+        groups
+            .into_iter()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(_, mut group)| {
+                if group.len() == 1 {
+                    group.remove(0).value
+                } else {
+                    Value::List(
+                        group.into_iter().map(|evaluated| evaluated.value).collect(),
+                        ListSeparator::Space
+                    )
+                }
+            })
+            .collect()
+    )
+}
+
+/// Rebuilds the text of `pair`, resolving references and user created value
+/// functions but keeping any arithmetic as written
+fn rebuild_pair(pair: Pair<Rule>, scope: &Scope, call_stack: &mut Vec<String>) -> Result<String> {
+    let text = pair.as_str();
+    let start = pair.as_span().start();
+    let mut result = String::new();
+    let mut last = 0;
+
+    for child in pair.clone().into_inner() {
+        let child_start = child.as_span().start() - start;
+        let child_end = child.as_span().end() - start;
+
+        result.push_str(&text[last..child_start]);
+
+        let child_text = match child.as_rule() {
+            Rule::variable_reference | Rule::del_val_keyword | Rule::function_call => {
+                let children = vec![child.clone()];
+
+                match tokenize(&child, children, scope, call_stack)?.pop() {
+                    Some(Item { token: Token::Operand { text, .. }, .. }) => text,
+                    _ => child.as_str().to_string(),
+                }
+            }
+
+            _ => rebuild_pair(child, scope, call_stack)?,
+        };
+
+        result.push_str(&child_text);
+        last = child_end;
+    }
+
+    result.push_str(&text[last..]);
+
+    Ok(result)
+}
+
+/// Evaluates a user created function with a return type at compile time
+#[allow(clippy::too_many_arguments)]
+fn call_value_function(
+    pair: Pair<Rule>,
+    func_name: &str,
+    parameters: &[functions::FunctionParameter],
+    return_type: &str,
+    body: &str,
+    file_path: &str,
+    captured: &[MetaData],
+    scope: &Scope,
+    call_stack: &mut Vec<String>
+) -> Result<(Value, String)> {
+    let position = pair.line_col();
+
+    let function_error = |message: String| {
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(scope.raw_rcss, line, 2);
+
+        let err = RCSSError::FunctionError {
+            file_path: scope.input_path.into(),
+            line,
+            column,
+            function_name: func_name.to_string(),
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    if call_stack.iter().any(|name| name == func_name) {
+        let mut chain = call_stack.clone();
+        chain.push(func_name.to_string());
+
+        return Err(function_error(format!("Recursive function call: {}", chain.join(" -> "))));
+    }
+
+    // Every comma separated group of the call is one argument
+    let children: Vec<Pair<Rule>> = pair.clone().into_inner().filter(is_value_token).collect();
+    let items = tokenize(&pair, children, scope, call_stack)?;
+    let groups = ExpressionParser { items: &items, position: 0, scope, divide: false }.parse()?;
+
+    let mut arguments: Vec<functions::FunctionArgument> = Vec::new();
+    let mut item_index = 0;
+
+    for (_, group) in groups {
+        if group.is_empty() {
+            continue;
+        }
+
+        let mut value = String::new();
+
+        for evaluated in group {
+            if !value.is_empty() {
+                value.push_str(&evaluated.gap);
+            }
+            value.push_str(&evaluated.text);
+        }
+
+        let line_col = items
+            .iter()
+            .skip(item_index)
+            .find(|item| !matches!(item.token, Token::Comma(_)))
+            .map_or(position, |item| item.line_col);
+
+        item_index = items
+            .iter()
+            .enumerate()
+            .skip(item_index + 1)
+            .find(|(_, item)| matches!(item.token, Token::Comma(_)))
+            .map_or(items.len(), |(index, _)| index);
+
+        arguments.push(functions::FunctionArgument { name: None, value, line_col });
+    }
+
+    let values = functions::bind_function_arguments(
+        func_name,
+        parameters,
+        arguments,
+        position,
+        scope.raw_rcss,
+        scope.input_path
+    )?;
+
+    let expression = match RCSSParser::parse(Rule::return_body, body) {
+        Ok(mut pairs) => pairs.next(),
+        Err(e) => {
+            return Err(function_error(format!("Could not evaluate function body: {}", e)));
+        }
+    };
+
+    let Some(expression) = expression else {
+        return Err(function_error("Function does not return a value".to_string()));
+    };
+
+    // Parameters shadow globals inside the body and may be written without `&`
+    let mut meta_data = scope.meta_data.to_vec();
+    let mut parameter_names = Vec::new();
+
+    meta_data.extend_from_slice(captured);
+
+    for (parameter, value) in parameters.iter().zip(values) {
+        meta_data.push(MetaData::Variables {
+            name: parameter.name.clone(),
+            value: Value::parse(&value),
+            public: false,
+        });
+        parameter_names.push(parameter.name.clone());
+    }
+
+    let body_scope = Scope {
+        meta_data: &meta_data,
+        bare_names: &parameter_names,
+        raw_rcss: body,
+        input_path: file_path,
+    };
+
+    call_stack.push(func_name.to_string());
+    let result = evaluate_container(expression, &body_scope, call_stack);
+    call_stack.pop();
+
+    let (value, text) = result?;
+
+    if !matches_return_type(&value, return_type) {
+        return Err(
+            function_error(
+                format!(
+                    "Function should return a {} but returned a {} ({})",
+                    return_type,
+                    value.type_name(),
+                    text.trim()
+                )
+            )
+        );
+    }
+
+    Ok((value, text.trim().to_string()))
+}
+
+/// Whether a value can be returned from a function declared with `-> return_type`
+fn matches_return_type(value: &Value, return_type: &str) -> bool {
+    match (return_type, value) {
+        ("any", _) | (_, Value::Raw(_)) => true,
+        ("length", Value::Length(_, unit)) => unit != "%",
+        ("length", Value::Number(number)) => *number == 0.0,
+        ("length" | "percentage", Value::Calc(_)) => true,
+        ("percentage", Value::Length(_, unit)) => unit == "%",
+        ("number", Value::Number(_)) => true,
+        ("color", Value::Color(_) | Value::Keyword(_)) => true,
+        ("string", Value::String(_)) => true,
+        ("keyword", Value::Keyword(_)) => true,
+        ("list", Value::List(..)) => true,
+        _ => false,
+    }
+}
+
+/// Precedence climbing over the tokens of a value. Space separated operands
+/// bind loosest, then `+ -`, then `* / %`.
+struct ExpressionParser<'a, 'b> {
+    items: &'a [Item],
+    position: usize,
+    scope: &'a Scope<'b>,
+
+    // Whether `/` divides, otherwise it is kept as written
+    divide: bool,
+}
+
+impl ExpressionParser<'_, '_> {
+    /// Returns each comma separated group along with the separator written before it
+    fn parse(&mut self) -> Result<Vec<(String, Vec<Evaluated>)>> {
+        let mut groups = Vec::new();
+        let mut separator = String::new();
+        let mut group = Vec::new();
+
+        while let Some(item) = self.items.get(self.position) {
+            if let Token::Comma(text) = &item.token {
+                groups.push((std::mem::take(&mut separator), std::mem::take(&mut group)));
+                separator = text.clone();
+                self.position += 1;
+                continue;
+            }
+
+            group.push(self.parse_additive()?);
+        }
+
+        groups.push((separator, group));
+
+        Ok(groups)
+    }
+
+    fn peek_operator(&self, operators: &[char]) -> Option<char> {
+        match self.items.get(self.position) {
+            Some(Item { token: Token::Operator(op), .. }) if operators.contains(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Evaluated> {
+        let items = self.items;
+        let mut lhs = self.parse_multiplicative()?;
+
+        while let Some(op) = self.peek_operator(&['+', '-']) {
+            let op_item = &items[self.position];
+
+            // `10px -&x` is two values, the second one negated
+            let is_unary = items
+                .get(self.position + 1)
+                .is_some_and(|next| !op_item.gap.is_empty() && next.gap.is_empty());
+
+            if is_unary {
+                break;
+            }
+
+            self.position += 1;
+            let rhs = self.parse_multiplicative_after(op_item)?;
+            lhs = self.apply(op, op_item, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Evaluated> {
+        let items = self.items;
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(op) = self.peek_operator(&['*', '/', '%']) {
+            let op_item = &items[self.position];
+            self.position += 1;
+            let rhs = self.parse_unary_after(op_item)?;
+            lhs = self.apply(op, op_item, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_multiplicative_after(&mut self, op_item: &Item) -> Result<Evaluated> {
+        self.expect_operand(op_item)?;
+        self.parse_multiplicative()
+    }
+
+    fn parse_unary_after(&mut self, op_item: &Item) -> Result<Evaluated> {
+        self.expect_operand(op_item)?;
+        self.parse_unary()
+    }
+
+    fn expect_operand(&self, op_item: &Item) -> Result<()> {
+        match self.items.get(self.position) {
+            Some(Item { token: Token::Comma(_), .. }) | None => {
+                Err(self.error(op_item, "Expected a value after this operator".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Evaluated> {
+        let items = self.items;
+        let item = &items[self.position];
+        self.position += 1;
+
+        match &item.token {
+            Token::Operand { value, text, computed } => {
+                Ok(Evaluated {
+                    value: value.clone(),
+                    text: text.clone(),
+                    computed: *computed,
+                    gap: item.gap.clone(),
+                    operator: None,
+                })
+            }
+
+            Token::Operator(op @ ('-' | '+')) => {
+                self.expect_operand(item)?;
+                let operand = self.parse_unary()?;
+
+                let negated = match (&operand.value, op) {
+                    (_, '+') => None,
+                    (Value::Number(number), _) => Some(Value::Number(-number)),
+                    (Value::Length(number, unit), _) => Some(Value::Length(-number, unit.clone())),
+                    _ => None,
+                };
+
+                match negated {
+                    Some(value) if operand.computed => {
+                        Ok(Evaluated {
+                            text: value.to_string(),
+                            value,
+                            computed: true,
+                            gap: item.gap.clone(),
+                            operator: None,
+                        })
+                    }
+
+                    // Kept as written (ex: -webkit-box)
+                    _ => {
+                        let text = format!("{}{}{}", op, operand.gap, operand.text);
+
+                        Ok(Evaluated {
+                            value: Value::Raw(text.clone()),
+                            text,
+                            computed: operand.computed,
+                            gap: item.gap.clone(),
+                            operator: None,
+                        })
+                    }
+                }
+            }
+
+            Token::Operator(op) => {
+                Err(self.error(item, format!("Expected a value before '{}'", op)))
+            }
+
+            Token::Comma(_) => Err(self.error(item, "Expected a value".to_string())),
+        }
+    }
+
+    fn apply(&self, op: char, op_item: &Item, lhs: Evaluated, rhs: Evaluated) -> Result<Evaluated> {
+        let computed = lhs.computed || rhs.computed;
+
+        let literal = |lhs: Evaluated, rhs: Evaluated| {
+            let text = format!("{}{}{}{}{}", lhs.text, op_item.gap, op, rhs.gap, rhs.text);
+
+            Evaluated { value: Value::Raw(text.clone()), text, computed, gap: lhs.gap, operator: Some(op) }
+        };
+
+        // A kept `/` separates values (ex: 1 / 3 / 2), mixing it with other
+        // arithmetic would mean guessing whether a division was meant
+        if !self.divide {
+            let operators = [lhs.operator, rhs.operator];
+            let mixed = if op == '/' {
+                operators.iter().any(|operator| operator.is_some_and(|operator| operator != '/'))
+            } else {
+                operators.contains(&Some('/'))
+            };
+
+            if mixed {
+                return Err(
+                    self.error(
+                        op_item,
+                        "Ambiguous '/': outside parentheses it separates values (ex: 12px/1.5), \
+                         wrap a division in parentheses to compute it".to_string()
+                    )
+                );
+            }
+
+            if op == '/' {
+                return Ok(literal(lhs, rhs));
+            }
+        }
+
+        // Strings are concatenated (ex: "icon-" + &name)
+        if op == '+' && let Value::String(left) = &lhs.value {
+            let right = match &rhs.value {
+                Value::String(string) => string.clone(),
+                value => value.to_string(),
+            };
+            let value = Value::String(format!("{}{}", left, right));
+
+            return Ok(Evaluated {
+                text: value.to_string(),
+                value,
+                computed: true,
+                gap: lhs.gap,
+                operator: Some(op),
+            });
+        }
+
+        let value = match (numeric(&lhs.value), numeric(&rhs.value)) {
+            (Some(left), Some(right)) => self.apply_numeric(op, op_item, left, right)?,
+
+            _ if op == '*' || op == '%' => {
+                return Err(
+                    self.error(
+                        op_item,
+                        format!(
+                            "Cannot apply '{}' to {} ({}) and {} ({})",
+                            op,
+                            lhs.value.type_name(),
+                            lhs.text,
+                            rhs.value.type_name(),
+                            rhs.text
+                        )
+                    )
+                );
+            }
+
+            _ => {
+                return Ok(literal(lhs, rhs));
+            }
+        };
+
+        Ok(Evaluated { text: value.to_string(), value, computed: true, gap: lhs.gap, operator: Some(op) })
+    }
+
+    fn apply_numeric(&self, op: char, op_item: &Item, left: Numeric, right: Numeric) -> Result<Value> {
+        let incompatible = |left: &Numeric, right: &Numeric| {
+            self.error(
+                op_item,
+                format!("Cannot apply '{}' to {} and {}", op, left.to_css(), right.to_css())
+            )
+        };
+
+        let value = match (op, &left, &right) {
+            // Anything involving calc() stays a calc() expression
+            (_, Numeric::Calc(_), _) | (_, _, Numeric::Calc(_)) => {
+                if op == '%' {
+                    return Err(incompatible(&left, &right));
+                }
+
+                Value::Calc(format!("{} {} {}", left.to_calc(op, false), op, right.to_calc(op, true)))
+            }
+
+            ('+' | '-', Numeric::Number(a), Numeric::Number(b)) => {
+                Value::Number(if op == '+' { a + b } else { a - b })
+            }
+
+            ('+' | '-', Numeric::Length(a, unit), Numeric::Number(b)) |
+            ('+' | '-', Numeric::Number(a), Numeric::Length(b, unit)) => {
+                Value::Length(if op == '+' { a + b } else { a - b }, unit.clone())
+            }
+
+            ('+' | '-', Numeric::Length(a, a_unit), Numeric::Length(b, b_unit)) => {
+                match convert_unit(*b, b_unit, a_unit) {
+                    Some(b) => Value::Length(if op == '+' { a + b } else { a - b }, a_unit.clone()),
+
+                    // Incompatible units (ex: 100% - 10px) are left for the browser
+                    None => Value::Calc(format!("{} {} {}", left.to_css(), op, right.to_css())),
+                }
+            }
+
+            ('*', Numeric::Number(a), Numeric::Number(b)) => Value::Number(a * b),
+
+            ('*', Numeric::Length(a, unit), Numeric::Number(b)) |
+            ('*', Numeric::Number(a), Numeric::Length(b, unit)) => {
+                Value::Length(a * b, unit.clone())
+            }
+
+            ('/' | '%', _, Numeric::Number(b)) if *b == 0.0 => {
+                return Err(self.error(op_item, "Division by zero".to_string()));
+            }
+
+            ('/' | '%', _, Numeric::Length(b, _)) if *b == 0.0 => {
+                return Err(self.error(op_item, "Division by zero".to_string()));
+            }
+
+            ('/', Numeric::Number(a), Numeric::Number(b)) => Value::Number(a / b),
+
+            ('/', Numeric::Length(a, unit), Numeric::Number(b)) => Value::Length(a / b, unit.clone()),
+
+            ('/', Numeric::Length(a, a_unit), Numeric::Length(b, b_unit)) => {
+                match convert_unit(*b, b_unit, a_unit) {
+                    Some(b) => Value::Number(a / b),
+                    None => {
+                        return Err(incompatible(&left, &right));
+                    }
+                }
+            }
+
+            ('%', Numeric::Number(a), Numeric::Number(b)) => Value::Number(a % b),
+
+            ('%', Numeric::Length(a, unit), Numeric::Number(b)) => Value::Length(a % b, unit.clone()),
+
+            ('%', Numeric::Length(a, a_unit), Numeric::Length(b, b_unit)) => {
+                match convert_unit(*b, b_unit, a_unit) {
+                    Some(b) => Value::Length(a % b, a_unit.clone()),
+                    None => {
+                        return Err(incompatible(&left, &right));
+                    }
+                }
+            }
+
+            // ex: 4px * 2em, 2 / 4px
+            _ => {
+                return Err(incompatible(&left, &right));
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn error(&self, item: &Item, message: String) -> RCSSError {
+        let line = item.line_col.0;
+        let column = item.line_col.1;
+        let context = get_error_context(self.scope.raw_rcss, line, 2);
+
+        let err = RCSSError::ExpressionError {
+            file_path: self.scope.input_path.into(),
+            line,
+            column,
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    }
+}
+
+/// A value that takes part in arithmetic
+enum Numeric {
+    Number(f64),
+    Length(f64, String),
+    Calc(String),
+}
+
+impl Numeric {
+    fn to_css(&self) -> String {
+        match self {
+            Numeric::Number(number) => Value::Number(*number).to_string(),
+            Numeric::Length(number, unit) => Value::Length(*number, unit.clone()).to_string(),
+            Numeric::Calc(expression) => format!("calc({})", expression),
+        }
+    }
+
+    /// The text of this operand inside a larger calc() expression
+    fn to_calc(&self, op: char, is_rhs: bool) -> String {
+        match self {
+            Numeric::Calc(expression) if op == '+' && !is_rhs => expression.clone(),
+            Numeric::Calc(expression) => format!("({})", expression),
+            numeric => numeric.to_css(),
+        }
+    }
+}
+
+fn numeric(value: &Value) -> Option<Numeric> {
+    match value {
+        Value::Number(number) => Some(Numeric::Number(*number)),
+        Value::Length(number, unit) => Some(Numeric::Length(*number, unit.clone())),
+        Value::Calc(expression) => Some(Numeric::Calc(expression.clone())),
+
+        // Values declared with `let x: "4px";` or returned from calc()
+        Value::Raw(text) => {
+            if let Some(expression) = text.strip_prefix("calc(").and_then(|t| t.strip_suffix(')')) {
+                return Some(Numeric::Calc(expression.to_string()));
+            }
+
+            match Value::parse(text) {
+                Value::Number(number) => Some(Numeric::Number(number)),
+                Value::Length(number, unit) => Some(Numeric::Length(number, unit)),
+                _ => None,
+            }
+        }
+
+        _ => None,
+    }
+}
+
+/// Orders two numbers or lengths, converting between compatible units
+pub fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    match (numeric(left)?, numeric(right)?) {
+        (Numeric::Number(a), Numeric::Number(b)) => a.partial_cmp(&b),
+        (Numeric::Length(a, a_unit), Numeric::Length(b, b_unit)) => {
+            a.partial_cmp(&convert_unit(b, &b_unit, &a_unit)?)
+        }
+        _ => None,
+    }
+}
+
+/// Converts `number` from one unit to another if they measure the same thing
+fn convert_unit(number: f64, from: &str, to: &str) -> Option<f64> {
+    if from == to {
+        return Some(number);
+    }
+
+    UNIT_GROUPS.iter().find_map(|group| {
+        let from_factor = group.iter().find(|(unit, _)| *unit == from)?.1;
+        let to_factor = group.iter().find(|(unit, _)| *unit == to)?.1;
+
+        Some((number * from_factor) / to_factor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(declaration: &str, meta_data: &[MetaData]) -> Result<String> {
+        let pair = RCSSParser::parse(Rule::declaration, declaration).unwrap().next().unwrap();
+        resolve_value(pair, meta_data, declaration, "test.rcss")
+    }
+
+    fn variable(name: &str, value: Value) -> MetaData {
+        MetaData::Variables { name: name.to_string(), value, public: false }
+    }
+
+    #[test]
+    fn converts_units_within_a_group() {
+        assert_eq!(convert_unit(1.0, "in", "px"), Some(96.0));
+        assert_eq!(convert_unit(2.0, "s", "ms"), Some(2000.0));
+        assert_eq!(convert_unit(0.5, "turn", "deg"), Some(180.0));
+        assert_eq!(convert_unit(3.0, "em", "em"), Some(3.0));
+        assert_eq!(convert_unit(1.0, "px", "deg"), None);
+        assert_eq!(convert_unit(1.0, "px", "em"), None);
+    }
+
+    #[test]
+    fn folds_arithmetic() {
+        assert_eq!(resolve("width: 4px * 3;", &[]).unwrap(), "width: 12px;");
+        assert_eq!(resolve("width: 4px + 1in;", &[]).unwrap(), "width: 100px;");
+        assert_eq!(resolve("width: (2 + 1) * 4px;", &[]).unwrap(), "width: 12px;");
+    }
+
+    #[test]
+    fn keeps_incompatible_units_as_calc() {
+        assert_eq!(resolve("width: 100% - 10px;", &[]).unwrap(), "width: calc(100% - 10px);");
+    }
+
+    #[test]
+    fn rejects_nonsense_arithmetic() {
+        assert!(resolve("width: 4px * 2em;", &[]).is_err());
+        assert!(resolve("width: (4px / 0);", &[]).is_err());
+    }
+
+    #[test]
+    fn keeps_top_level_slash() {
+        let meta_data = [
+            variable("fs", Value::Length(16.0, "px".to_string())),
+            variable("start", Value::Number(1.0)),
+            variable("end", Value::Number(3.0)),
+        ];
+
+        assert_eq!(resolve("font: 12px/1.5 sans-serif;", &meta_data).unwrap(), "font: 12px/1.5 sans-serif;");
+        assert_eq!(resolve("font: &fs/1.5 sans-serif;", &meta_data).unwrap(), "font: 16px/1.5 sans-serif;");
+        assert_eq!(resolve("grid-row: &start / &end;", &meta_data).unwrap(), "grid-row: 1 / 3;");
+        assert_eq!(resolve("grid-area: 1 / 2 / 3 / 4;", &meta_data).unwrap(), "grid-area: 1 / 2 / 3 / 4;");
+    }
+
+    #[test]
+    fn divides_inside_parentheses_only() {
+        let meta_data = [variable("fs", Value::Length(16.0, "px".to_string()))];
+
+        assert_eq!(resolve("width: (&fs / 4);", &meta_data).unwrap(), "width: 4px;");
+        assert!(resolve("width: &fs * 2 / 3;", &meta_data).is_err());
+        assert!(resolve("width: &fs / 2 + 1px;", &meta_data).is_err());
+    }
+}
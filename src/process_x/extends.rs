@@ -0,0 +1,168 @@
+use pest::iterators::Pair;
+use crate::{
+    compile::Rule,
+    error::{ display_error, RCSSError, get_error_context },
+    expressions,
+    rule_normal::{ Declarations, MediaQueries },
+    MetaData,
+    Result,
+};
+
+/// An `impl .btn for .btn-primary;` statement, applied once every rule is known
+pub struct Extend {
+    pub base: String,
+    pub extender: String,
+    pub line_col: (usize, usize),
+}
+
+pub fn process_extend_statement(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Extend> {
+    let line_col = pair.line_col();
+    let mut selectors = Vec::new();
+
+    for in_pair in pair.into_inner() {
+        let selector = expressions::interpolate(&in_pair, meta_data, raw_rcss, input_path)?;
+        selectors.push(selector.trim().to_string());
+    }
+
+    let mut selectors = selectors.into_iter();
+
+    Ok(Extend {
+        base: selectors.next().unwrap_or_default(),
+        extender: selectors.next().unwrap_or_default(),
+        line_col,
+    })
+}
+
+/// Appends the extending selector to every rule that uses the base selector,
+/// ex: `.btn:hover { ... }` becomes `.btn:hover, .btn-primary:hover { ... }`
+pub fn apply_extends(
+    mut declarations: Declarations,
+    mut media_queries: MediaQueries,
+    extends: &[Extend],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<(Declarations, MediaQueries)> {
+    for extend in extends {
+        let mut found = false;
+
+        declarations = extend_rules(declarations, extend, &mut found);
+
+        media_queries = media_queries
+            .into_iter()
+            .map(|(condition, rules)| (condition, extend_rules(rules, extend, &mut found)))
+            .collect();
+
+        if !found {
+            let (line, column) = extend.line_col;
+            let context = get_error_context(raw_rcss, line, 2);
+
+            let err = RCSSError::SelectorError {
+                file_path: input_path.into(),
+                line,
+                column,
+                selector: extend.base.clone(),
+                message: format!(
+                    "Selector {} is never defined, so {} has nothing to inherit",
+                    extend.base,
+                    extend.extender
+                ),
+                context,
+            };
+
+            display_error(&err);
+            return Err(err);
+        }
+    }
+
+    Ok((declarations, media_queries))
+}
+
+fn extend_rules(rules: Declarations, extend: &Extend, found: &mut bool) -> Declarations {
+    let mut extended = Declarations::new();
+
+    for (selector, properties) in rules {
+        let mut selectors: Vec<String> = selector
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .collect();
+
+        let inherited: Vec<String> = selectors
+            .iter()
+            .filter_map(|s| replace_selector(s, &extend.base, &extend.extender))
+            .collect();
+
+        for selector in inherited {
+            *found = true;
+
+            if !selectors.contains(&selector) {
+                selectors.push(selector);
+            }
+        }
+
+        extended.entry(selectors.join(", ")).or_default().extend(properties);
+    }
+
+    extended
+}
+
+/// Replaces every use of `base` as a whole compound part of `selector`, so
+/// `.btn` matches `.card .btn:hover` but not `.btn-large`
+fn replace_selector(selector: &str, base: &str, extender: &str) -> Option<String> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+
+    // Classes, ids and pseudo-classes can follow anything, a type selector has to start a compound
+    let needs_boundary = !base.starts_with(['.', '#', ':', '[']);
+
+    let mut result = String::new();
+    let mut position = 0;
+    let mut replaced = false;
+
+    while let Some(index) = selector[position..].find(base) {
+        let start = position + index;
+        let end = start + base.len();
+
+        let before = selector[..start].chars().last();
+        let after = selector[end..].chars().next();
+
+        let matches = !after.is_some_and(is_name_char) &&
+            (!needs_boundary || !before.is_some_and(|c| is_name_char(c) || ".#:".contains(c)));
+
+        result.push_str(&selector[position..start]);
+        result.push_str(if matches { extender } else { base });
+
+        replaced |= matches;
+        position = end;
+    }
+
+    result.push_str(&selector[position..]);
+
+    replaced.then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_whole_compound_parts() {
+        assert_eq!(
+            replace_selector(".card .btn:hover", ".btn", ".btn-primary"),
+            Some(".card .btn-primary:hover".to_string())
+        );
+        assert_eq!(replace_selector(".btn.active", ".btn", ".link"), Some(".link.active".to_string()));
+        assert_eq!(replace_selector(".btn-large", ".btn", ".link"), None);
+        assert_eq!(replace_selector(".card", ".btn", ".link"), None);
+    }
+
+    #[test]
+    fn type_selectors_start_a_compound() {
+        assert_eq!(replace_selector("nav a:hover", "a", ".link"), Some("nav .link:hover".to_string()));
+        assert_eq!(replace_selector(".a", "a", ".link"), None);
+        assert_eq!(replace_selector("abbr", "a", ".link"), None);
+    }
+}
@@ -1,5 +1,26 @@
 use pest::iterators::Pair;
-use crate::{ compile::Rule, MetaData, error::{ RCSSError, get_error_context, display_error } };
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::{
+    compile::Rule,
+    MetaData,
+    error::{ RCSSError, get_error_context, display_error },
+    expressions,
+};
+
+#[derive(Debug, Clone)]
+pub struct FunctionParameter {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// An argument at a call site, either positional (`4px`) or named (`y: 4px`)
+#[derive(Debug, Clone)]
+pub struct FunctionArgument {
+    pub name: Option<String>,
+    pub value: String,
+    pub line_col: (usize, usize),
+}
 
 pub fn process_function_definition(
     mut meta_data: Vec<MetaData>,
@@ -13,92 +34,302 @@ pub fn process_function_definition(
     let inner_pairs = pair.into_inner();
 
     let mut name = String::new();
-    let mut declerations: Vec<String> = vec![];
+    let mut parameters: Vec<FunctionParameter> = vec![];
+    let mut return_type: Option<String> = None;
+    let mut body = String::new();
+    let mut public = false;
 
     for in_pair in inner_pairs {
         match in_pair.as_rule() {
+            Rule::visibility => {
+                public = true;
+            }
+
             Rule::function_name => {
                 name = in_pair.as_str().trim().to_string();
             }
 
-            Rule::function_block => {
-                let function_block_inner_pairs = in_pair.into_inner();
+            Rule::parameter_list => {
+                for param_pair in in_pair.into_inner() {
+                    let position = param_pair.line_col();
+                    let mut parameter = FunctionParameter {
+                        name: String::new(),
+                        default: None,
+                    };
 
-                for func_in_pair in function_block_inner_pairs {
-                    match func_in_pair.as_rule() {
-                        Rule::declaration => {
-                            let declaration_inner = func_in_pair.clone().into_inner();
-                            let mut variable_reference = String::new();
+                    for param_in_pair in param_pair.into_inner() {
+                        match param_in_pair.as_rule() {
+                            Rule::identifier => {
+                                parameter.name = param_in_pair.as_str().to_string();
+                            }
 
-                            for dec_in_pair in declaration_inner {
-                                match dec_in_pair.as_rule() {
-                                    Rule::variable_reference => {
-                                        variable_reference = dec_in_pair.as_str().to_string();
-                                    }
+                            Rule::parameter_default => {
+                                // Bound exactly like a value passed as an argument
+                                let default = expressions::resolve_value(
+                                    param_in_pair,
+                                    &meta_data,
+                                    raw_rcss,
+                                    input_path
+                                )?;
 
-                                    _ => {}
-                                }
+                                parameter.default = Some(default);
                             }
 
-                            let default_value = func_in_pair.as_str().trim().to_string();
-
-                            if !variable_reference.is_empty() {
-                                let mut found_var = false;
-
-                                for md in &meta_data {
-                                    if let MetaData::Variables { name, value } = md {
-                                        if name == variable_reference.trim_start_matches('&') {
-                                            found_var = true;
-
-                                            let replaced_value = default_value.replace(
-                                                &variable_reference,
-                                                value
-                                            );
-                                            declerations.push(replaced_value);
-                                        }
-                                    }
-                                }
-
-                                if !found_var {
-                                    let position = func_in_pair.line_col();
-                                    let line = position.0;
-                                    let column = position.1;
-                                    let context = get_error_context(raw_rcss, line, 2);
-
-                                    let err = RCSSError::VariableError {
-                                        file_path: input_path.into(),
-                                        line,
-                                        column,
-                                        variable_name: variable_reference
-                                            .trim_start_matches("&")
-                                            .to_string(),
-                                        message: format!(
-                                            "Could not find variable: {}",
-                                            variable_reference.trim_start_matches("&")
-                                        ),
-                                        context,
-                                    };
-
-                                    display_error(&err);
-                                    return Err(err);
-                                }
-                            } else {
-                                declerations.push(default_value);
-                            }
+                            _ => {}
                         }
+                    }
 
-                        _ => {}
+                    if parameters.iter().any(|p| p.name == parameter.name) {
+                        let line = position.0;
+                        let column = position.1;
+                        let context = get_error_context(raw_rcss, line, 2);
+
+                        let err = RCSSError::FunctionError {
+                            file_path: input_path.into(),
+                            line,
+                            column,
+                            function_name: name,
+                            message: format!("Duplicate parameter: {}", parameter.name),
+                            context,
+                        };
+
+                        display_error(&err);
+                        return Err(err);
+                    }
+
+                    if parameter.default.is_none() && parameters.iter().any(|p| p.default.is_some()) {
+                        let line = position.0;
+                        let column = position.1;
+                        let context = get_error_context(raw_rcss, line, 2);
+
+                        let err = RCSSError::FunctionError {
+                            file_path: input_path.into(),
+                            line,
+                            column,
+                            function_name: name,
+                            message: format!(
+                                "Parameter {} must have a default value because it follows a parameter with one",
+                                parameter.name
+                            ),
+                            context,
+                        };
+
+                        display_error(&err);
+                        return Err(err);
                     }
+
+                    parameters.push(parameter);
                 }
             }
 
-            Rule::parameter_list => {}
+            Rule::return_type => {
+                return_type = in_pair
+                    .into_inner()
+                    .next()
+                    .map(|p| p.as_str().to_string());
+            }
+
+            Rule::function_block | Rule::return_block => {
+                body = pad_block(&in_pair);
+            }
 
             _ => {}
         }
     }
 
-    meta_data.push(MetaData::Function { name, body: declerations });
+    meta_data.push(MetaData::Function {
+        name,
+        parameters,
+        return_type,
+        body,
+        file_path: input_path.to_string(),
+        public,
+        scope: Rc::from([]),
+    });
 
     Ok(meta_data)
 }
+
+/// Binds positional, named and default values to the parameters of a function,
+/// returning one value per parameter in declaration order
+pub fn bind_function_arguments(
+    func_name: &str,
+    parameters: &[FunctionParameter],
+    arguments: Vec<FunctionArgument>,
+    call_line_col: (usize, usize),
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Vec<String>, RCSSError> {
+    let function_error = |line_col: (usize, usize), message: String| {
+        let line = line_col.0;
+        let column = line_col.1;
+        let context = get_error_context(raw_rcss, line, 2);
+
+        let err = RCSSError::FunctionError {
+            file_path: input_path.into(),
+            line,
+            column,
+            function_name: func_name.to_string(),
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let mut bound: HashMap<&str, String> = HashMap::new();
+    let mut positional_index = 0;
+
+    for argument in arguments {
+        let parameter = match &argument.name {
+            Some(name) => {
+                if bound.contains_key(name.as_str()) {
+                    return Err(
+                        function_error(
+                            argument.line_col,
+                            format!("Argument {} was supplied more than once", name)
+                        )
+                    );
+                }
+
+                let Some(parameter) = parameters.iter().find(|p| p.name == *name) else {
+                    let expected: Vec<&str> = parameters
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect();
+
+                    return Err(
+                        function_error(
+                            argument.line_col,
+                            format!(
+                                "Function {} has no parameter named {} (expected one of: {})",
+                                func_name,
+                                name,
+                                expected.join(", ")
+                            )
+                        )
+                    );
+                };
+
+                parameter
+            }
+
+            None => {
+                if !bound.is_empty() && bound.len() != positional_index {
+                    return Err(
+                        function_error(
+                            argument.line_col,
+                            "Positional arguments must come before named arguments".to_string()
+                        )
+                    );
+                }
+
+                let Some(parameter) = parameters.get(positional_index) else {
+                    return Err(
+                        function_error(
+                            argument.line_col,
+                            format!(
+                                "Function {} takes {} argument(s) but more were supplied",
+                                func_name,
+                                parameters.len()
+                            )
+                        )
+                    );
+                };
+
+                positional_index += 1;
+                parameter
+            }
+        };
+
+        bound.insert(&parameter.name, argument.value);
+    }
+
+    let mut values = Vec::new();
+    let mut missing = Vec::new();
+
+    for parameter in parameters {
+        match bound.remove(parameter.name.as_str()).or_else(|| parameter.default.clone()) {
+            Some(value) => values.push(value),
+            None => missing.push(parameter.name.as_str()),
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(
+            function_error(
+                call_line_col,
+                format!("Missing argument(s) for {}: {}", func_name, missing.join(", "))
+            )
+        );
+    }
+
+    Ok(values)
+}
+
+/// Returns the inside of a `{ ... }` block, padded so that positions inside it
+/// still match the defining file when the body is re-parsed at the call site
+pub fn pad_block(pair: &Pair<Rule>) -> String {
+    let block = pair.as_str();
+    let (line, column) = pair.line_col();
+
+    format!("{}{} {}", "\n".repeat(line - 1), " ".repeat(column - 1), &block[1..block.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> Vec<FunctionParameter> {
+        vec![
+            FunctionParameter { name: "x".to_string(), default: None },
+            FunctionParameter { name: "y".to_string(), default: Some("8px".to_string()) },
+        ]
+    }
+
+    fn argument(name: Option<&str>, value: &str) -> FunctionArgument {
+        FunctionArgument {
+            name: name.map(|name| name.to_string()),
+            value: value.to_string(),
+            line_col: (1, 1),
+        }
+    }
+
+    fn bind(arguments: Vec<FunctionArgument>) -> Result<Vec<String>, RCSSError> {
+        bind_function_arguments("padding", &parameters(), arguments, (1, 1), "", "test.rcss")
+    }
+
+    #[test]
+    fn binds_positional_and_named_arguments() {
+        assert_eq!(bind(vec![argument(None, "2px"), argument(None, "4px")]).unwrap(), ["2px", "4px"]);
+        assert_eq!(
+            bind(vec![argument(Some("y"), "4px"), argument(Some("x"), "2px")]).unwrap(),
+            ["2px", "4px"]
+        );
+        assert_eq!(bind(vec![argument(None, "2px"), argument(Some("y"), "4px")]).unwrap(), ["2px", "4px"]);
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        assert_eq!(bind(vec![argument(None, "2px")]).unwrap(), ["2px", "8px"]);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        // Missing x
+        assert!(bind(vec![argument(Some("y"), "4px")]).is_err());
+
+        // Too many
+        assert!(bind(vec![argument(None, "1px"), argument(None, "2px"), argument(None, "3px")]).is_err());
+
+        // Unknown name
+        assert!(bind(vec![argument(None, "1px"), argument(Some("z"), "2px")]).is_err());
+
+        // Supplied twice
+        assert!(bind(vec![argument(Some("x"), "1px"), argument(Some("x"), "2px")]).is_err());
+
+        // Positional after named
+        assert!(bind(vec![argument(Some("y"), "1px"), argument(None, "2px")]).is_err());
+    }
+}
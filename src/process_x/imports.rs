@@ -1,11 +1,208 @@
-use pest::iterators::Pair;
+use pest::{ iterators::{ Pair, Pairs }, Parser };
 use crate::{
-    compile::Rule,
+    compile::{ Rule, RCSSParser },
     error::{ display_error, RCSSError, get_error_context },
     MetaData,
     Result,
 };
 use std::collections::HashMap;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+
+/// A single item named in an import (ex: `primary` or `accent as brand`)
+struct ImportItem {
+    name: String,
+    alias: Option<String>,
+    line_col: (usize, usize),
+}
+
+/// The parts of a `use` or `include` statement
+struct ImportPath {
+    segments: Vec<String>,
+    items: Vec<ImportItem>,
+    import_all: bool,
+    grouped: bool,
+    public: bool,
+}
+
+impl ImportPath {
+    /// `use common;` may name a whole file rather than an item in it
+    fn may_name_file(&self) -> bool {
+        matches!(self.items.as_slice(), [item] if !self.grouped && item.alias.is_none())
+    }
+
+    /// The path with the single item it names, if it could be a file
+    fn file_segments(&self) -> Option<Vec<String>> {
+        if !self.may_name_file() {
+            return None;
+        }
+
+        let mut segments = self.segments.clone();
+        segments.extend(self.items.iter().map(|item| item.name.clone()));
+        Some(segments)
+    }
+}
+
+/// Finds the file an import reads, and whether the import names that whole file
+/// (ex: `use common;`) rather than items in it (ex: `use common::{gap};`)
+fn import_target(
+    path: &ImportPath,
+    input_path: &str,
+    relative_path: &str
+) -> std::result::Result<(String, bool), String> {
+    if let Some(segments) = path.file_segments() {
+        let (base, segments) = module_base(&segments, input_path, relative_path)?;
+
+        if let Some(file) = module_file(&base, segments) {
+            return Ok((file, true));
+        }
+    }
+
+    let (base, segments) = module_base(&path.segments, input_path, relative_path)?;
+
+    module_file(&base, segments)
+        .map(|file| (file, false))
+        .ok_or("File not found".to_string())
+}
+
+/// The files a file reads items from through `use`, `include` or a path
+/// (ex: &colors::primary), which have to be compiled before it
+pub fn dependencies(
+    pairs: Pairs<Rule>,
+    input_path: &str,
+    relative_path: &str
+) -> Vec<(String, (usize, usize))> {
+    let mut files: Vec<(String, (usize, usize))> = Vec::new();
+
+    for pair in pairs.clone().flatten() {
+        if !matches!(pair.as_rule(), Rule::import_statement | Rule::include_statement) {
+            continue;
+        }
+
+        let path = parse_import_path(&pair);
+
+        if
+            let Ok((file, _)) = import_target(&path, input_path, relative_path) &&
+            !files.iter().any(|(known, _)| *known == file)
+        {
+            files.push((file, pair.line_col()));
+        }
+    }
+
+    for (path, line_col) in qualified_references(pairs) {
+        let segments: Vec<String> = path.split("::").map(|s| s.to_string()).collect();
+
+        let Some((_, module)) = segments.split_last() else {
+            continue;
+        };
+
+        if
+            let Ok((base, segments)) = module_base(module, input_path, relative_path) &&
+            let Some(file) = module_file(&base, segments) &&
+            !files.iter().any(|(known, _)| *known == file)
+        {
+            files.push((file, line_col));
+        }
+    }
+
+    files
+}
+
+/// How a file is written in a `use` (ex: components/button.rcss -> components::button)
+pub fn module_name(file: &str, relative_path: &str) -> String {
+    let path = file.strip_prefix(relative_path).unwrap_or(file);
+    let path = path.trim_start_matches('/').trim_end_matches(".rcss");
+    let path = path.strip_suffix("/mod").unwrap_or(path);
+
+    path.replace('/', "::")
+}
+
+/// Finds the file an `include` names (ex: include components::button;)
+pub fn resolve_include(
+    pair: &Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str,
+    relative_path: &str
+) -> Result<String> {
+    let path = parse_import_path(pair);
+
+    let file = match import_target(&path, input_path, relative_path) {
+        Ok((file, true)) => Ok(file),
+        Ok(_) => Err("include takes the path of a file, not items in it".to_string()),
+        Err(message) => Err(message),
+    };
+
+    match file {
+        Ok(file) => Ok(file),
+        Err(message) => {
+            let position = pair.line_col();
+            let line = position.0;
+            let column = position.1;
+            let context = get_error_context(raw_rcss, line, 2);
+
+            let err = RCSSError::ImportError {
+                file_path: input_path.into(),
+                line,
+                column,
+                message,
+                context,
+            };
+
+            display_error(&err);
+            Err(err)
+        }
+    }
+}
+
+/// Reads the plain CSS file a `use` names (ex: use vendor::normalize; for
+/// vendor/normalize.css), when no RCSS module of that name exists
+pub fn process_css_import(
+    pair: &Pair<Rule>,
+    input_path: &str,
+    relative_path: &str
+) -> Result<Option<(String, String)>> {
+    let Some(segments) = parse_import_path(pair).file_segments() else {
+        return Ok(None);
+    };
+
+    let Ok((base, segments)) = module_base(&segments, input_path, relative_path) else {
+        return Ok(None);
+    };
+
+    if module_file(&base, segments).is_some() {
+        return Ok(None);
+    }
+
+    let path = segments.iter().fold(base, |path, segment| path.join(segment));
+    let css_path = format!("{}.css", path.display());
+
+    if !Path::new(&css_path).is_file() {
+        return Ok(None);
+    }
+
+    let css = fs::read_to_string(&css_path)?;
+
+    if let Err(e) = RCSSParser::parse(Rule::css_file, &css) {
+        let (line, column) = match e.line_col {
+            pest::error::LineColLocation::Pos((line, col)) => (line, col),
+            pest::error::LineColLocation::Span((line, col), _) => (line, col),
+        };
+
+        let err = RCSSError::ParseError {
+            file_path: css_path.into(),
+            line,
+            column,
+            message: format!("{}", e),
+            context: get_error_context(&css, line, 2),
+        };
+
+        display_error(&err);
+        return Err(err);
+    }
+
+    Ok(Some((css_path, css.trim().to_string())))
+}
 
 pub fn process_import_statement(
     meta_data: &mut Vec<MetaData>,
@@ -15,41 +212,279 @@ pub fn process_import_statement(
     relative_path: &str,
     pair: Pair<Rule>
 ) -> Result<Vec<MetaData>> {
-    let inner_pairs = pair.clone().into_inner();
-    let mut target_import_file: Vec<String> = Vec::new();
+    let path = parse_import_path(&pair);
+
+    let import_error = |line_col: (usize, usize), message: String| {
+        let line = line_col.0;
+        let column = line_col.1;
+        let context = get_error_context(raw_rcss, line, 2);
+
+        let err = RCSSError::ImportError {
+            file_path: input_path.into(),
+            line,
+            column,
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let (file, names_file) = match import_target(&path, input_path, relative_path) {
+        Ok(target) => target,
+        Err(message) => {
+            return Err(import_error(pair.line_col(), message));
+        }
+    };
+
+    let Some(imported_meta_data) = project_meta_data.get(&file) else {
+        return Err(import_error(pair.line_col(), "File not found".to_string()));
+    };
+
+    let file_scope: Rc<[MetaData]> = Rc::from(imported_meta_data.as_slice());
+
+    // use common; and use common::*; bring in every public item of the file
+    if names_file || path.import_all {
+        meta_data.extend(
+            file_scope
+                .iter()
+                .filter(|data| data.is_public())
+                .map(|data| data.clone().imported(&file_scope, path.public))
+        );
+
+        return Ok(meta_data.clone());
+    }
+
+    let module = path.segments.join("::");
+
+    for item in path.items {
+        let found = match public_items(&file_scope, &module, &item.name, path.public) {
+            Ok(found) => found,
+            Err(message) => {
+                return Err(import_error(item.line_col, message));
+            }
+        };
+
+        let local_name = item.alias.as_deref().unwrap_or(&item.name);
+
+        meta_data.extend(found.into_iter().map(|data| data.renamed(local_name)));
+    }
+
+    Ok(meta_data.clone())
+}
+
+/// Brings in the items a file names by path without a `use` (ex: &colors::primary,
+/// mixins::padding();), under that same path so they never clash with local names
+pub fn import_qualified_references(
+    mut meta_data: Vec<MetaData>,
+    project_meta_data: &HashMap<String, Vec<MetaData>>,
+    raw_rcss: &str,
+    input_path: &str,
+    relative_path: &str,
+    pairs: Pairs<Rule>
+) -> Result<Vec<MetaData>> {
+    for (path, line_col) in qualified_references(pairs) {
+        let segments: Vec<String> = path.split("::").map(|s| s.to_string()).collect();
+
+        let Some((item_name, module)) = segments.split_last() else {
+            continue;
+        };
+
+        let found = module_base(module, input_path, relative_path)
+            .and_then(|(base, segments)| {
+                module_file(&base, segments)
+                    .and_then(|file| project_meta_data.get(&file))
+                    .ok_or(format!("File not found for {}", path))
+            })
+            .and_then(|imported_meta_data| {
+                let file_scope: Rc<[MetaData]> = Rc::from(imported_meta_data.as_slice());
+
+                public_items(&file_scope, &module.join("::"), item_name, false)
+            });
+
+        match found {
+            Ok(found) => {
+                meta_data.extend(found.into_iter().map(|data| data.renamed(&path)));
+            }
+            Err(message) => {
+                let line = line_col.0;
+                let column = line_col.1;
+                let context = get_error_context(raw_rcss, line, 2);
+
+                let err = RCSSError::ImportError {
+                    file_path: input_path.into(),
+                    line,
+                    column,
+                    message,
+                    context,
+                };
 
-    for import_in_pair in inner_pairs {
+                display_error(&err);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(meta_data)
+}
+
+/// The paths a file names items of other files by, with where each first appears
+fn qualified_references(pairs: Pairs<Rule>) -> Vec<(String, (usize, usize))> {
+    let mut references: Vec<(String, (usize, usize))> = Vec::new();
+
+    for pair in pairs.flatten() {
+        let reference = match pair.as_rule() {
+            Rule::variable_reference | Rule::interpolation => {
+                pair.as_str().trim_matches(|c| c == '{' || c == '}' || c == '&')
+            }
+            Rule::function_name | Rule::function_call => pair.as_str(),
+            _ => {
+                continue;
+            }
+        };
+
+        // Map fields and call arguments are not part of the path
+        let path = reference.split(['.', '(']).next().unwrap_or_default();
+
+        if path.contains("::") && !references.iter().any(|(known, _)| known == path) {
+            references.push((path.to_string(), pair.line_col()));
+        }
+    }
+
+    references
+}
+
+/// Finds the folder a module path starts from: crate:: starts at the project root,
+/// self:: at the folder of the importing file and every super:: goes up one more
+fn module_base<'a>(
+    segments: &'a [String],
+    input_path: &str,
+    relative_path: &str
+) -> std::result::Result<(PathBuf, &'a [String]), String> {
+    let root = Path::new(relative_path);
+    let mut base = root.to_path_buf();
+    let mut segments = segments;
+
+    if let Some(first) = segments.first() && (first == "self" || first == "super") {
+        base = Path::new(input_path).parent().unwrap_or(root).to_path_buf();
+
+        if first == "self" {
+            segments = &segments[1..];
+        }
+
+        while let Some((first, rest)) = segments.split_first() && first == "super" {
+            if !base.pop() || !base.starts_with(root) {
+                return Err("super:: goes above the project root".to_string());
+            }
+
+            segments = rest;
+        }
+    } else if segments.first().is_some_and(|first| first == "crate") {
+        segments = &segments[1..];
+    }
+
+    Ok((base, segments))
+}
+
+/// A module is either name.rcss or a folder with a mod.rcss
+fn module_file(base: &Path, segments: &[String]) -> Option<String> {
+    let path = segments.iter().fold(base.to_path_buf(), |path, segment| path.join(segment));
+
+    [format!("{}.rcss", path.display()), path.join("mod.rcss").display().to_string()]
+        .into_iter()
+        .find(|candidate| Path::new(candidate).is_file())
+}
+
+/// The public items of a module with the given name, ready to add to the importing
+/// file (and to pass on to its importers when re-exported with `pub use`)
+fn public_items(
+    file_scope: &Rc<[MetaData]>,
+    module: &str,
+    item_name: &str,
+    re_export: bool
+) -> std::result::Result<Vec<MetaData>, String> {
+    let found: Vec<&MetaData> = file_scope
+        .iter()
+        .filter(|data| data.name() == item_name)
+        .collect();
+
+    if found.is_empty() {
+        return Err(format!("{} has no item named {}", module, item_name));
+    }
+
+    if !found.iter().any(|data| data.is_public()) {
+        return Err(
+            format!("{} is private to {} (declare it with pub to import it)", item_name, module)
+        );
+    }
+
+    Ok(
+        found
+            .into_iter()
+            .filter(|data| data.is_public())
+            .map(|data| data.clone().imported(file_scope, re_export))
+            .collect()
+    )
+}
+
+fn parse_import_path(pair: &Pair<Rule>) -> ImportPath {
+    let mut path = ImportPath {
+        segments: Vec::new(),
+        items: Vec::new(),
+        import_all: false,
+        grouped: false,
+        public: false,
+    };
+
+    for import_in_pair in pair.clone().into_inner() {
         match import_in_pair.as_rule() {
+            Rule::visibility => {
+                path.public = true;
+            }
+
             Rule::identifier => {
-                target_import_file.push(import_in_pair.as_str().to_string());
+                path.segments.push(import_in_pair.as_str().to_string());
+            }
+
+            Rule::import_item => {
+                path.items.push(parse_import_item(import_in_pair));
+            }
+
+            Rule::import_group => {
+                path.grouped = true;
+                path.items.extend(import_in_pair.into_inner().map(parse_import_item));
+            }
+
+            Rule::import_all => {
+                path.import_all = true;
             }
 
             _ => {}
         }
     }
 
-    //TODO - redo this better.. it sucks rn
-    let full_path = format!("{}/{}", relative_path, target_import_file.join("/")) + ".rcss";
+    path
+}
 
-    if let Some(imported_meta_data) = project_meta_data.get(&full_path) {
-        meta_data.extend(imported_meta_data.clone());
-    } else {
-        let position = pair.line_col();
-        let line = position.0;
-        let column = position.1;
-        let context = get_error_context(raw_rcss, line, 2);
+fn parse_import_item(pair: Pair<Rule>) -> ImportItem {
+    let line_col = pair.line_col();
+    let mut name = String::new();
+    let mut alias = None;
 
-        let err = RCSSError::ImportError {
-            file_path: input_path.into(),
-            line: line,
-            column: column,
-            message: "File not found".to_string(),
-            context: context,
-        };
+    for in_pair in pair.into_inner() {
+        match in_pair.as_rule() {
+            Rule::identifier => {
+                name = in_pair.as_str().to_string();
+            }
 
-        display_error(&err);
-        return Err(err);
+            Rule::import_alias => {
+                alias = Some(in_pair.as_str().to_string());
+            }
+
+            _ => {}
+        }
     }
 
-    Ok(meta_data.clone())
+    ImportItem { name, alias, line_col }
 }
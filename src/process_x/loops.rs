@@ -0,0 +1,206 @@
+use pest::iterators::Pair;
+use crate::{
+    compile::Rule,
+    error::{ display_error, RCSSError, get_error_context },
+    expressions,
+    value::Value,
+    MetaData,
+    Result,
+};
+
+/// The number of iterations a single loop may run when no limit is given
+pub const DEFAULT_MAX_ITERATIONS: usize = 1000;
+
+/// A `for` loop ready to be expanded: the loop variables, the values they take
+/// on each iteration and the block to repeat
+pub struct ForLoop<'a> {
+    pub variables: Vec<String>,
+    pub iterations: Vec<Vec<Value>>,
+    pub body: Pair<'a, Rule>,
+}
+
+pub fn process_for_statement<'a>(
+    pair: Pair<'a, Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str,
+    max_iterations: usize
+) -> Result<ForLoop<'a>> {
+    let position = pair.line_col();
+
+    let loop_error = |line_col: (usize, usize), message: String| {
+        let line = line_col.0;
+        let column = line_col.1;
+        let context = get_error_context(raw_rcss, line, 2);
+
+        let err = RCSSError::ExpressionError {
+            file_path: input_path.into(),
+            line,
+            column,
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let mut variables = Vec::new();
+    let mut iterations = Vec::new();
+    let mut body = None;
+
+    for in_pair in pair.into_inner() {
+        match in_pair.as_rule() {
+            Rule::identifier => {
+                variables = vec![in_pair.as_str().to_string()];
+            }
+
+            // ex: (name, color)
+            Rule::for_pattern => {
+                variables = in_pair
+                    .into_inner()
+                    .map(|p| p.as_str().to_string())
+                    .collect();
+            }
+
+            // ex: 1..=12, &start..&end
+            Rule::for_range => {
+                let mut bounds = Vec::new();
+                let mut inclusive = false;
+
+                for range_pair in in_pair.into_inner() {
+                    if range_pair.as_rule() == Rule::range_operator {
+                        inclusive = range_pair.as_str() == "..=";
+                        continue;
+                    }
+
+                    let line_col = range_pair.line_col();
+                    let text = range_pair.as_str().to_string();
+
+                    let bound = match
+                        expressions::evaluate_value(range_pair, meta_data, raw_rcss, input_path)?
+                    {
+                        Value::Number(number) if number.fract() == 0.0 => {
+                            // `as` would silently saturate anything past the i64 range
+                            if number < (i64::MIN as f64) || number >= (i64::MAX as f64) {
+                                return Err(
+                                    loop_error(
+                                        line_col,
+                                        format!("Range bound {} is out of range", text)
+                                    )
+                                );
+                            }
+
+                            number as i64
+                        }
+                        value => {
+                            return Err(
+                                loop_error(
+                                    line_col,
+                                    format!(
+                                        "Range bounds must be whole numbers but found {} ({})",
+                                        value.type_name(),
+                                        value
+                                    )
+                                )
+                            );
+                        }
+                    };
+
+                    bounds.push(bound);
+                }
+
+                let (Some(&start), Some(&end)) = (bounds.first(), bounds.last()) else {
+                    continue;
+                };
+
+                // Widened so neither the inclusive end nor the count can overflow
+                let (start, end) = (i128::from(start), i128::from(end) + i128::from(inclusive));
+                let count = usize::try_from((end - start).max(0)).unwrap_or(usize::MAX);
+
+                if count > max_iterations {
+                    return Err(too_many_iterations(&loop_error, position, count, max_iterations));
+                }
+
+                iterations = (start..end).map(|number| vec![Value::Number(number as f64)]).collect();
+            }
+
+            // ex: &sizes, sm md lg, &theme
+            Rule::variable_value => {
+                let line_col = in_pair.line_col();
+                let value = expressions::evaluate_value(in_pair, meta_data, raw_rcss, input_path)?;
+
+                iterations = match (value, variables.len()) {
+                    // Maps give their keys and values (ex: for (name, color) in &theme)
+                    (Value::Map(entries), 2) => {
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| vec![Value::Keyword(key), value])
+                            .collect()
+                    }
+
+                    (Value::Map(_), _) => {
+                        return Err(
+                            loop_error(
+                                line_col,
+                                "Loops over a map need a name for the key and the value (ex: for (key, value) in &map)".to_string()
+                            )
+                        );
+                    }
+
+                    (value, 2) => {
+                        return Err(
+                            loop_error(
+                                line_col,
+                                format!("Only maps can be looped over as (key, value) but found {} ({})", value.type_name(), value)
+                            )
+                        );
+                    }
+
+                    (Value::List(items, _), _) => {
+                        items
+                            .into_iter()
+                            .map(|item| vec![item])
+                            .collect()
+                    }
+
+                    (value, _) => vec![vec![value]],
+                };
+
+                if iterations.len() > max_iterations {
+                    return Err(
+                        too_many_iterations(&loop_error, position, iterations.len(), max_iterations)
+                    );
+                }
+            }
+
+            Rule::top_block => {
+                body = Some(in_pair);
+            }
+
+            _ => {}
+        }
+    }
+
+    let Some(body) = body else {
+        return Err(loop_error(position, "Loop has no body".to_string()));
+    };
+
+    Ok(ForLoop { variables, iterations, body })
+}
+
+fn too_many_iterations(
+    loop_error: &impl Fn((usize, usize), String) -> RCSSError,
+    position: (usize, usize),
+    iterations: usize,
+    max_iterations: usize
+) -> RCSSError {
+    loop_error(
+        position,
+        format!(
+            "Loop would run {} times, more than the limit of {} (raise it with --max-iterations)",
+            iterations,
+            max_iterations
+        )
+    )
+}
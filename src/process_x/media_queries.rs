@@ -1,5 +1,5 @@
 use pest::iterators::Pair;
-use crate::{ compile::Rule, error::Result, process_x::rule_normal, MetaData };
+use crate::{ compile::Rule, error::Result, process_x::{ expressions, rule_normal, variables }, MetaData };
 use std::collections::HashMap;
 
 pub fn process_media_query(
@@ -13,18 +13,39 @@ pub fn process_media_query(
 ) -> Result<HashMap<String, HashMap<String, Vec<String>>>> {
     let inner_pairs = pair.into_inner();
     let mut condition = String::new();
-    let mut declarations: HashMap<String, Vec<String>> = HashMap::new();
+
+    // Variables declared inside the media query are only visible to its rules
+    let mut meta_data = meta_data.clone();
 
     for inner_pair in inner_pairs {
         match inner_pair.as_rule() {
             Rule::media_condition => {
-                condition = format!("@media {}", inner_pair.as_str().trim());
+                let media_condition = expressions::interpolate(
+                    &inner_pair,
+                    &meta_data,
+                    raw_rcss,
+                    input_path
+                )?;
+
+                condition = format!("@media {}", media_condition.trim());
+                media_queries.entry(condition.clone()).or_default();
+            }
+
+            Rule::variable_declaration => {
+                meta_data = variables::process_variable_declaration(
+                    meta_data,
+                    inner_pair,
+                    raw_rcss,
+                    input_path
+                )?;
             }
 
             Rule::rule_normal => {
-                declarations = rule_normal::process_rule_normal(
+                (_, media_queries) = rule_normal::process_rule_normal(
                     meta_data.clone(),
-                    declarations,
+                    HashMap::new(),
+                    media_queries,
+                    Some(&condition),
                     inner_pair,
                     raw_rcss,
                     input_path
@@ -35,7 +56,5 @@ pub fn process_media_query(
         }
     }
 
-    media_queries.insert(condition, declarations);
-
     Ok(media_queries)
 }
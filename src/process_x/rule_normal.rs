@@ -1,169 +1,612 @@
-use pest::iterators::Pair;
+use pest::{ iterators::Pair, Parser };
 use crate::{
-    compile::Rule,
+    compile::{ Rule, RCSSParser },
+    control_flow,
     error::{ display_error, RCSSError, get_error_context },
+    functions,
+    expressions,
+    value::Value,
+    variables,
     MetaData,
     Result,
 };
 use std::collections::HashMap;
 
+/// Selector -> declarations
+pub type Declarations = HashMap<String, Vec<String>>;
+
+/// Media condition -> selector -> declarations
+pub type MediaQueries = HashMap<String, Declarations>;
+
+/// State shared while walking a rule, the functions it calls and the media queries it nests
+struct RuleContext {
+    meta_data: Vec<MetaData>,
+    current_selector: Vec<String>,
+    scope_stack: Vec<usize>,
+    media_condition: Option<String>,
+    declarations: Declarations,
+    media_queries: MediaQueries,
+    call_stack: Vec<String>,
+    content_blocks: Vec<ContentBlock>,
+}
+
+/// A block passed to a function with `name!{ ... }`, placed wherever the body
+/// names the parameter that receives it
+struct ContentBlock {
+    parameter: String,
+    body: String,
+    file_path: String,
+
+    // The variables and blocks visible at the call, which the block is evaluated with
+    scope_len: usize,
+    visible_blocks: usize,
+}
+
 pub fn process_rule_normal(
     meta_data: Vec<MetaData>,
-    mut declarations: HashMap<String, Vec<String>>,
+    declarations: Declarations,
+    media_queries: MediaQueries,
+    media_condition: Option<&str>,
     pair: Pair<Rule>,
     raw_rcss: &str,
     input_path: &str
-) -> Result<HashMap<String, Vec<String>>> {
-    let inner_pairs = pair.into_inner();
-    let mut current_selector: Vec<String> = Vec::new();
+) -> Result<(Declarations, MediaQueries)> {
+    let mut context = RuleContext {
+        meta_data,
+        current_selector: Vec::new(),
+        scope_stack: Vec::new(),
+        media_condition: media_condition.map(|condition| condition.to_string()),
+        declarations,
+        media_queries,
+        call_stack: Vec::new(),
+        content_blocks: Vec::new(),
+    };
 
-    for in_pair in inner_pairs {
+    if pair.as_rule() == Rule::impl_statement {
+        process_trait_impl(&mut context, pair, raw_rcss, input_path)?;
+    } else {
+        process_rule_content(&mut context, pair.into_inner(), raw_rcss, input_path)?;
+    }
+
+    Ok((context.declarations, context.media_queries))
+}
+
+fn process_rule_content<'a>(
+    context: &mut RuleContext,
+    pairs: impl Iterator<Item = Pair<'a, Rule>>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<()> {
+    for in_pair in pairs {
         match in_pair.as_rule() {
             Rule::selector => {
-                // if not the pseduo thing
-                let selector_str = in_pair.as_str().trim();
-
-                if selector_str.starts_with("&::") || selector_str.starts_with("&:") {
-                    // Handle parent selector reference with pseudo-elements/classes
-                    if let Some(last) = current_selector.last_mut() {
-                        // Append the pseudo-element/class to the parent selector
-                        *last = format!("{}{}", last, selector_str.trim_start_matches('&'));
-                    } else {
-                        // This shouldn't happen with valid RCSS, but handle it gracefully
-                        current_selector.push(selector_str.trim_start_matches('&').to_string());
-                    }
-                } else {
-                    // Regular selector (no parent reference)
-                    current_selector.push(selector_str.to_string());
-                }
+                let selector_str = expressions::interpolate(
+                    &in_pair,
+                    &context.meta_data,
+                    raw_rcss,
+                    input_path
+                )?;
+
+                let selector = combine_selectors(
+                    context.current_selector.last().map(|parent| parent.as_str()),
+                    selector_str.trim()
+                );
+
+                context.current_selector.push(selector);
+                context.scope_stack.push(context.meta_data.len());
             }
 
             Rule::right_curly_brace => {
-                if let Some(last) = current_selector.last_mut() {
-                    // Split by either ':' or '::', whichever comes first
-                    let mut split_idx = None;
-
-                    if let Some(idx) = last.find("::") {
-                        split_idx = Some(idx);
-                    } else if let Some(idx) = last.find(':') {
-                        // Ensure it's the first ':' from the left, not part of '::'
-                        if last.get(idx..idx + 2) != Some("::") {
-                            split_idx = Some(idx);
-                        }
-                    }
+                context.current_selector.pop();
 
-                    if let Some(idx) = split_idx {
-                        *last = last[..idx].to_string();
-                        continue;
-                    }
+                // Variables declared inside the block are dropped at its closing brace
+                if let Some(scope_start) = context.scope_stack.pop() {
+                    context.meta_data.truncate(scope_start);
                 }
+            }
 
-                if !current_selector.is_empty() {
-                    current_selector.pop();
-                }
+            Rule::variable_declaration => {
+                context.meta_data = variables::process_variable_declaration(
+                    std::mem::take(&mut context.meta_data),
+                    in_pair,
+                    raw_rcss,
+                    input_path
+                )?;
             }
 
             Rule::declaration => {
-                // Extract "color" and "height" from the declaration
-                let mut decl_str = in_pair.as_str().trim().to_string();
-                // Split by ':' to get property and value
+                let decl_str = expressions::resolve_declaration(
+                    in_pair,
+                    &context.meta_data,
+                    raw_rcss,
+                    input_path
+                )?;
 
-                let mut referenced_vars = Vec::new();
+                push_declarations(context, vec![decl_str]);
+            }
 
-                if let Some((_property, value)) = decl_str.split_once(':') {
-                    for token in value.split_whitespace() {
-                        if token.starts_with("&") {
-                            let var = token
-                                .trim_start_matches('&')
-                                .trim_end_matches(|c| (c == ';' || c == ',' || c == ')'));
+            Rule::nested_media_query => {
+                let mut media_inner_pairs = in_pair.into_inner();
 
-                            referenced_vars.push(var);
-                        }
+                let condition = match media_inner_pairs.next() {
+                    Some(condition) => {
+                        expressions::interpolate(
+                            &condition,
+                            &context.meta_data,
+                            raw_rcss,
+                            input_path
+                        )?
                     }
-                }
+                    None => String::new(),
+                };
+                let condition = condition.trim();
 
-                // Collect all replacements to avoid borrowing issues
-                let mut replacements = Vec::new();
-                for data in &meta_data {
-                    if let MetaData::Variables { name, value: var_value } = data {
-                        if referenced_vars.contains(&name.as_str()) {
-                            replacements.push((format!("&{}", name), var_value.clone()));
-                            referenced_vars.retain(|v| v != name);
-                        }
-                    }
-                }
+                let nested_condition = match &context.media_condition {
+                    Some(outer) => format!("{} and {}", outer, condition),
+                    None => format!("@media {}", condition),
+                };
+
+                let outer_condition = context.media_condition.replace(nested_condition);
+                let scope_start = context.meta_data.len();
+
+                process_rule_content(context, media_inner_pairs, raw_rcss, input_path)?;
+
+                context.meta_data.truncate(scope_start);
+                context.media_condition = outer_condition;
+            }
+
+            Rule::user_created_function_call | Rule::content_block_call => {
+                process_function_call(context, in_pair, raw_rcss, input_path)?;
+            }
+
+            Rule::content_slot => {
+                process_content_slot(context, in_pair, raw_rcss, input_path)?;
+            }
+
+            Rule::if_statement | Rule::match_statement => {
+                let branch = control_flow::select_branch(
+                    in_pair,
+                    &context.meta_data,
+                    raw_rcss,
+                    input_path
+                )?;
+
+                if let Some(block) = branch {
+                    let scope_start = context.meta_data.len();
+
+                    process_rule_content(context, block.into_inner(), raw_rcss, input_path)?;
 
-                for (pattern, replacement) in replacements {
-                    decl_str = decl_str.replace(&pattern, &replacement);
+                    context.meta_data.truncate(scope_start);
                 }
+            }
 
-                let joined_selector = current_selector.join(" ");
-                let key = joined_selector.trim();
+            _ => {}
+        }
+    }
 
-                if let Some(values) = declarations.get_mut(key) {
-                    values.push(decl_str.clone());
-                } else {
-                    declarations.insert(key.to_string(), vec![decl_str.clone()]);
+    Ok(())
+}
+
+/// Expands a user created function call relative to the calling selector
+fn process_function_call(
+    context: &mut RuleContext,
+    pair: Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<()> {
+    let position = pair.line_col();
+    let mut func_name = String::new();
+    let mut arguments: Vec<functions::FunctionArgument> = Vec::new();
+    let mut content_block: Option<String> = None;
+
+    for ucfunc_in_pair in pair.into_inner() {
+        match ucfunc_in_pair.as_rule() {
+            Rule::function_name => {
+                func_name = ucfunc_in_pair.as_str().trim().to_string();
+            }
+
+            Rule::argument_list => {
+                for arg_pair in ucfunc_in_pair.into_inner() {
+                    arguments.push(
+                        resolve_argument(arg_pair, &context.meta_data, raw_rcss, input_path)?
+                    );
                 }
             }
 
-            Rule::user_created_function_call => {
-                let user_created_func_inner_pairs = in_pair.clone().into_inner();
-                let mut func_name = String::new();
-                let mut func_declarations: Vec<String> = Vec::new();
+            Rule::content_block => {
+                content_block = Some(functions::pad_block(&ucfunc_in_pair));
+            }
 
-                for ucfunc_in_pair in user_created_func_inner_pairs {
-                    match ucfunc_in_pair.as_rule() {
-                        Rule::function_name => {
-                            func_name = ucfunc_in_pair.as_str().trim().to_string();
-                        }
+            _ => {}
+        }
+    }
 
-                        _ => {}
-                    }
+    let function = context.meta_data
+        .iter()
+        .rev()
+        .find_map(|data| {
+            match data {
+                MetaData::Function { name, parameters, return_type, body, file_path, scope, .. } if
+                    *name == func_name
+                => Some((parameters.clone(), return_type.clone(), body.clone(), file_path.clone(), scope.clone())),
+                _ => None,
+            }
+        });
+
+    let function_error = |message: String| {
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(raw_rcss, line, 2);
+
+        let err = RCSSError::FunctionError {
+            file_path: input_path.into(),
+            function_name: func_name.clone(),
+            message,
+            line,
+            column,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let Some((parameters, return_type, body, file_path, captured)) = function else {
+        return Err(function_error("Function not declared in scope".to_string()));
+    };
+
+    if let Some(return_type) = return_type {
+        return Err(
+            function_error(
+                format!(
+                    "Function returns a value ({}) and can only be used inside a declaration",
+                    return_type
+                )
+            )
+        );
+    }
+
+    if context.call_stack.contains(&func_name) {
+        let mut chain = context.call_stack.clone();
+        chain.push(func_name.clone());
+
+        return Err(function_error(format!("Recursive function call: {}", chain.join(" -> "))));
+    }
+
+    // The content block is bound to the last parameter
+    let (parameters, block_parameter) = match (&content_block, parameters.split_last()) {
+        (None, _) => (parameters.as_slice(), None),
+        (Some(_), Some((block_parameter, parameters))) => (parameters, Some(block_parameter)),
+        (Some(_), None) => {
+            return Err(
+                function_error(
+                    "Function takes no parameters, so it cannot receive a block (add a last parameter to receive it)".to_string()
+                )
+            );
+        }
+    };
+
+    let values = functions::bind_function_arguments(
+        &func_name,
+        parameters,
+        arguments,
+        position,
+        raw_rcss,
+        input_path
+    )?;
+
+    let body_pairs = match RCSSParser::parse(Rule::function_body, &body) {
+        Ok(p) => p,
+        Err(e) => {
+            return Err(function_error(format!("Could not expand function body: {}", e)));
+        }
+    };
+
+    // Parameters are pushed as variables so they shadow globals for the body only
+    let scope_start = context.meta_data.len();
+
+    context.meta_data.extend(captured.iter().cloned());
+
+    for (parameter, value) in parameters.iter().zip(values) {
+        context.meta_data.push(MetaData::Variables {
+            name: parameter.name.clone(),
+            value: Value::parse(&value),
+            public: false,
+        });
+    }
+
+    let blocks_len = context.content_blocks.len();
+
+    if let (Some(block_parameter), Some(content_block)) = (block_parameter, content_block) {
+        context.content_blocks.push(ContentBlock {
+            parameter: block_parameter.name.clone(),
+            body: content_block,
+            file_path: input_path.to_string(),
+            scope_len: scope_start,
+            visible_blocks: blocks_len,
+        });
+    }
+
+    context.call_stack.push(func_name);
+    let result = process_rule_content(context, body_pairs, &body, &file_path);
+    context.call_stack.pop();
+
+    context.content_blocks.truncate(blocks_len);
+    context.meta_data.truncate(scope_start);
+
+    result
+}
+
+/// Expands `impl Trait for .selector { ... }` as a rule holding the impl's own
+/// content followed by the trait body
+fn process_trait_impl(
+    context: &mut RuleContext,
+    pair: Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<()> {
+    let position = pair.line_col();
+    let mut inner_pairs = pair.into_inner();
+
+    let trait_name = inner_pairs
+        .next()
+        .map_or(String::new(), |p| p.as_str().to_string());
+
+    let trait_error = |message: String| {
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(raw_rcss, line, 2);
+
+        let err = RCSSError::TraitError {
+            file_path: input_path.into(),
+            line,
+            column,
+            trait_name: trait_name.clone(),
+            message,
+            context,
+        };
+
+        display_error(&err);
+        err
+    };
+
+    let found = context.meta_data
+        .iter()
+        .rev()
+        .find_map(|data| {
+            match data {
+                MetaData::Trait { name, associated_variables, body, file_path, scope, .. } if
+                    *name == trait_name
+                => Some((associated_variables.clone(), body.clone(), file_path.clone(), scope.clone())),
+                _ => None,
+            }
+        });
+
+    let Some((associated_variables, body, file_path, captured)) = found else {
+        return Err(trait_error("Trait not declared in scope".to_string()));
+    };
+
+    for in_pair in inner_pairs {
+        match in_pair.as_rule() {
+            Rule::selector => {
+                process_rule_content(context, std::iter::once(in_pair), raw_rcss, input_path)?;
+            }
+
+            Rule::impl_block => {
+                let scope_start = context.scope_stack.last().copied().unwrap_or(0);
+
+                process_rule_content(context, in_pair.into_inner(), raw_rcss, input_path)?;
+
+                let missing: Vec<&str> = associated_variables
+                    .iter()
+                    .filter(|variable| {
+                        !context.meta_data[scope_start..]
+                            .iter()
+                            .any(|data| matches!(data, MetaData::Variables { name, .. } if name == *variable))
+                    })
+                    .map(|variable| variable.as_str())
+                    .collect();
+
+                if !missing.is_empty() {
+                    return Err(
+                        trait_error(
+                            format!(
+                                "Missing associated variables for {}: {}",
+                                trait_name,
+                                missing.join(", ")
+                            )
+                        )
+                    );
                 }
 
-                for data in &meta_data {
-                    if let MetaData::Function { name, body } = data {
-                        if func_name == *name {
-                            func_declarations = body.clone();
-                        }
+                let body_pairs = match RCSSParser::parse(Rule::function_body, &body) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        return Err(trait_error(format!("Could not expand trait body: {}", e)));
                     }
-                }
+                };
 
-                if func_declarations.is_empty() {
-                    let position = in_pair.line_col();
-                    let line = position.0;
-                    let column = position.1;
-                    let context = get_error_context(raw_rcss, line, 2);
+                // The trait's own file comes before the impl's variables so they still win
+                context.meta_data.splice(scope_start..scope_start, captured.iter().cloned());
 
-                    let err = RCSSError::FunctionError {
-                        file_path: input_path.to_string().into(),
-                        function_name: func_name,
-                        message: "Function not declared in scope".to_string(),
-                        line: line,
-                        column: column,
-                        context: context,
-                    };
+                process_rule_content(context, body_pairs, &body, &file_path)?;
+            }
 
-                    display_error(&err);
+            // Closes the impl's selector and drops its variables
+            _ => {
+                process_rule_content(context, std::iter::once(in_pair), raw_rcss, input_path)?;
+            }
+        }
+    }
 
-                    return Err(err);
-                }
+    Ok(())
+}
+
+/// Expands the block passed to the function whose body is being processed
+fn process_content_slot(
+    context: &mut RuleContext,
+    pair: Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<()> {
+    let name = pair.as_str().trim().trim_end_matches(';').trim();
+
+    let Some(index) = context.content_blocks.iter().rposition(|block| block.parameter == name) else {
+        let func_name = context.call_stack.last().map_or("function", |f| f.as_str());
+        let message = format!(
+            "{} is not a block passed to this function (call it with {}!{{ ... }})",
+            name,
+            func_name
+        );
+
+        let position = pair.line_col();
+        let line = position.0;
+        let column = position.1;
+        let context = get_error_context(raw_rcss, line, 2);
 
-                let joined_selector = current_selector.join(" ");
+        let err = RCSSError::VariableError {
+            file_path: input_path.into(),
+            line,
+            column,
+            variable_name: name.to_string(),
+            message,
+            context,
+        };
 
-                let key = joined_selector.trim();
+        display_error(&err);
+        return Err(err);
+    };
 
-                if let Some(values) = declarations.get_mut(key) {
-                    values.extend(func_declarations.clone());
+    let block = &context.content_blocks[index];
+    let body = block.body.clone();
+    let file_path = block.file_path.clone();
+    let scope_len = block.scope_len;
+    let visible_blocks = block.visible_blocks;
+
+    let body_pairs = match RCSSParser::parse(Rule::function_body, &body) {
+        Ok(p) => p,
+        Err(e) => {
+            let position = pair.line_col();
+            let line = position.0;
+            let column = position.1;
+            let context = get_error_context(raw_rcss, line, 2);
+
+            let err = RCSSError::FunctionError {
+                file_path: input_path.into(),
+                line,
+                column,
+                function_name: name.to_string(),
+                message: format!("Could not expand block: {}", e),
+                context,
+            };
+
+            display_error(&err);
+            return Err(err);
+        }
+    };
+
+    // The block sees the variables and blocks of its call site, not those of the function
+    let hidden_meta_data = context.meta_data.split_off(scope_len);
+    let hidden_blocks = context.content_blocks.split_off(visible_blocks);
+
+    let result = process_rule_content(context, body_pairs, &body, &file_path);
+
+    context.meta_data.truncate(scope_len);
+    context.meta_data.extend(hidden_meta_data);
+    context.content_blocks.truncate(visible_blocks);
+    context.content_blocks.extend(hidden_blocks);
+
+    result
+}
+
+/// Resolves a nested selector against its parent, expanding selector lists on
+/// either side (ex: "a, b" + "&:hover, &:focus" -> "a:hover, a:focus, b:hover, b:focus")
+fn combine_selectors(parent: Option<&str>, selector: &str) -> String {
+    let selectors = selector.split(',').map(|s| s.trim());
+
+    let Some(parent) = parent else {
+        return selectors
+            .map(|s| s.trim_start_matches('&'))
+            .collect::<Vec<_>>()
+            .join(", ");
+    };
+
+    let mut combined = Vec::new();
+
+    for parent in parent.split(',').map(|s| s.trim()) {
+        for selector in selectors.clone() {
+            combined.push(
+                // Parent selector reference with pseudo-elements/classes (ex: &:hover)
+                if selector.contains('&') {
+                    selector.replace('&', parent)
                 } else {
-                    declarations.insert(key.to_string(), func_declarations.clone());
+                    format!("{} {}", parent, selector)
                 }
-            }
-
-            _ => {}
+            );
         }
     }
 
-    Ok(declarations)
+    combined.join(", ")
+}
+
+/// Adds declarations to the current selector, inside the current media query if any
+fn push_declarations(context: &mut RuleContext, new_declarations: Vec<String>) {
+    let key = context.current_selector.last().map_or("", |s| s.trim()).to_string();
+
+    let declarations = match &context.media_condition {
+        Some(condition) => context.media_queries.entry(condition.clone()).or_default(),
+        None => &mut context.declarations,
+    };
+
+    declarations.entry(key).or_default().extend(new_declarations);
+}
+
+/// Resolves the name and variable references of a single function call argument
+fn resolve_argument(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<functions::FunctionArgument> {
+    let line_col = pair.line_col();
+
+    let name = pair
+        .clone()
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::argument_name)
+        .map(|p| p.as_str().to_string());
+
+    let mut value = expressions::resolve_value(pair, meta_data, raw_rcss, input_path)?;
+
+    // Everything after "name:" is the value
+    if name.is_some() && let Some((_name, named_value)) = value.split_once(':') {
+        value = named_value.trim().to_string();
+    }
+
+    Ok(functions::FunctionArgument { name, value, line_col })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combines_top_level_selectors() {
+        assert_eq!(combine_selectors(None, ".a, &.b"), ".a, .b");
+    }
+
+    #[test]
+    fn nests_under_the_parent() {
+        assert_eq!(combine_selectors(Some(".card"), "p"), ".card p");
+        assert_eq!(combine_selectors(Some(".btn"), "&:hover"), ".btn:hover");
+        assert_eq!(combine_selectors(Some(".btn"), ".dark &"), ".dark .btn");
+    }
+
+    #[test]
+    fn expands_selector_lists() {
+        assert_eq!(
+            combine_selectors(Some("a, b"), "&:hover, &:focus"),
+            "a:hover, a:focus, b:hover, b:focus"
+        );
+    }
 }
@@ -0,0 +1,78 @@
+use pest::iterators::Pair;
+use std::rc::Rc;
+use crate::{
+    compile::Rule,
+    error::{ display_error, RCSSError, get_error_context },
+    MetaData,
+    Result,
+};
+
+pub fn process_trait_definition(
+    mut meta_data: Vec<MetaData>,
+    pair: Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Vec<MetaData>> {
+    let mut name = String::new();
+    let mut associated_variables: Vec<String> = Vec::new();
+    let mut body = String::new();
+    let mut public = false;
+
+    for in_pair in pair.into_inner() {
+        match in_pair.as_rule() {
+            Rule::visibility => {
+                public = true;
+            }
+
+            Rule::trait_name => {
+                name = in_pair.as_str().to_string();
+            }
+
+            // ex: let hover_color;
+            Rule::associated_variable => {
+                let (line, column) = in_pair.line_col();
+                let variable = in_pair
+                    .into_inner()
+                    .next()
+                    .map_or(String::new(), |p| p.as_str().to_string());
+
+                if associated_variables.contains(&variable) {
+                    let context = get_error_context(raw_rcss, line, 2);
+
+                    let err = RCSSError::TraitError {
+                        file_path: input_path.into(),
+                        line,
+                        column,
+                        trait_name: name,
+                        message: format!("Duplicate associated variable: {}", variable),
+                        context,
+                    };
+
+                    display_error(&err);
+                    return Err(err);
+                }
+
+                associated_variables.push(variable);
+            }
+
+            // Padded like a function body so positions still match this file
+            Rule::trait_body => {
+                let (line, column) = in_pair.line_col();
+                body = format!("{}{}{}", "\n".repeat(line - 1), " ".repeat(column - 1), in_pair.as_str());
+            }
+
+            _ => {}
+        }
+    }
+
+    meta_data.push(MetaData::Trait {
+        name,
+        associated_variables,
+        body,
+        file_path: input_path.to_string(),
+        public,
+        scope: Rc::from([]),
+    });
+
+    Ok(meta_data)
+}
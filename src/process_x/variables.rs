@@ -1,36 +1,100 @@
 use pest::iterators::Pair;
-use crate::{ MetaData, compile::Rule };
+use crate::{ compile::Rule, expressions, value::Value, MetaData, Result };
 
 pub fn process_variable_declaration(
     mut meta_data: Vec<MetaData>,
-    pair: Pair<Rule>
-) -> Vec<MetaData> {
+    pair: Pair<Rule>,
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Vec<MetaData>> {
     let inner_pairs = pair.into_inner();
 
     let mut name = String::new();
-    let mut value = String::new();
+    let mut value: Option<Value> = None;
+    let mut public = false;
 
     for in_pair in inner_pairs {
         match in_pair.as_rule() {
+            Rule::visibility => {
+                public = true;
+            }
+
             Rule::variable_name => {
                 name = in_pair.as_str().to_string();
             }
 
+            // let name: "value";
             Rule::string_literal => {
-                value = in_pair.as_str().to_string();
+                value = Some(Value::Raw(in_pair.as_str().trim_matches('"').to_string()));
+            }
+
+            // let name = { key: value };
+            Rule::map_literal => {
+                value = Some(expressions::evaluate_value(in_pair, &meta_data, raw_rcss, input_path)?);
+            }
+
+            // let name = value;
+            Rule::variable_value => {
+                value = Some(resolve_variable_value(in_pair, &meta_data, raw_rcss, input_path)?);
             }
 
             _ => {}
         }
     }
 
-    if name.is_empty() || value.is_empty() {
-        return meta_data;
+    let Some(value) = value else {
+        return Ok(meta_data);
+    };
+
+    if name.is_empty() {
+        return Ok(meta_data);
     }
 
-    value = value.trim_matches('"').to_string();
+    meta_data.push(MetaData::Variables { name, value, public });
+
+    Ok(meta_data)
+}
 
-    meta_data.push(MetaData::Variables { name: name, value: value });
+/// Converts a `variable_value` into a typed value, resolving the variables and
+/// user created value functions it refers to
+fn resolve_variable_value(
+    pair: Pair<Rule>,
+    meta_data: &[MetaData],
+    raw_rcss: &str,
+    input_path: &str
+) -> Result<Value> {
+    let has_unresolved = pair
+        .clone()
+        .into_inner()
+        .any(|p| matches!(p.as_rule(), Rule::variable_reference | Rule::function_call));
 
+    if !has_unresolved {
+        return Ok(Value::from_pair(pair));
+    }
+
+    // A lone reference keeps the type of the variable it points to
+    let mut items = pair.clone().into_inner();
+
+    if
+        let (Some(item), None) = (items.next(), items.next()) &&
+        item.as_rule() == Rule::variable_reference &&
+        let Some(value) = find_variable(meta_data, item.as_str().trim_start_matches('&'))
+    {
+        return Ok(value.clone());
+    }
+
+    expressions::evaluate_value(pair, meta_data, raw_rcss, input_path)
+}
+
+/// Finds the innermost (most recently declared) variable with the given name
+pub fn find_variable<'a>(meta_data: &'a [MetaData], variable_name: &str) -> Option<&'a Value> {
     meta_data
+        .iter()
+        .rev()
+        .find_map(|data| {
+            match data {
+                MetaData::Variables { name, value, .. } if name == variable_name => Some(value),
+                _ => None,
+            }
+        })
 }
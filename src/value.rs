@@ -0,0 +1,215 @@
+use std::fmt;
+use pest::{ iterators::Pair, Parser };
+use crate::compile::{ Rule, RCSSParser };
+
+/// A typed variable value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A unitless number (ex: 1.5)
+    Number(f64),
+    /// A number with a unit, including percentages (ex: 4px, 50%)
+    Length(f64, String),
+    /// A hex colour, or the rgba() produced by a colour built-in (ex: #3b82f6)
+    Color(String),
+    /// A quoted string, stored without its quotes
+    String(String),
+    /// A bare identifier (ex: red, auto, sans-serif)
+    Keyword(String),
+    /// Arithmetic between incompatible units left for the browser, stored
+    /// without the surrounding `calc()` (ex: 100% - 10px)
+    Calc(String),
+    /// Space or comma separated values (ex: 4px 8px)
+    List(Vec<Value>, ListSeparator),
+    /// Named values in the order they were written (ex: { primary: #fff, radius: 6px })
+    Map(Vec<(String, Value)>),
+    /// CSS text that is passed through as written (ex: `let x: "rgba(0, 0, 0, 0.3)";`)
+    Raw(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListSeparator {
+    Space,
+    Comma,
+}
+
+impl Value {
+    /// Converts a parsed `variable_value` (or one of its items) into a typed value
+    pub fn from_pair(pair: Pair<Rule>) -> Value {
+        match pair.as_rule() {
+            Rule::variable_value | Rule::map_value | Rule::list_item => {
+                if
+                    let Some(inner) = pair.clone().into_inner().next() &&
+                    inner.as_rule() == Rule::map_literal
+                {
+                    return Value::from_pair(inner);
+                }
+
+                let text = pair.as_str();
+                let start = pair.as_span().start();
+                let mut groups: Vec<Vec<Value>> = Vec::new();
+                let mut last = 0;
+
+                for item in pair.clone().into_inner() {
+                    let item_start = item.as_span().start() - start;
+                    let gap = &text[last..item_start];
+                    last = item.as_span().end() - start;
+
+                    match groups.last_mut() {
+                        Some(group) if !gap.contains(',') => group.push(Value::from_pair(item)),
+                        _ => groups.push(vec![Value::from_pair(item)]),
+                    }
+                }
+
+                let mut groups: Vec<Value> = groups
+                    .into_iter()
+                    .map(|mut group| {
+                        if group.len() == 1 {
+                            group.remove(0)
+                        } else {
+                            Value::List(group, ListSeparator::Space)
+                        }
+                    })
+                    .collect();
+
+                if groups.len() == 1 {
+                    groups.remove(0)
+                } else {
+                    Value::List(groups, ListSeparator::Comma)
+                }
+            }
+
+            Rule::list_literal => {
+                Value::List(pair.into_inner().map(Value::from_pair).collect(), ListSeparator::Comma)
+            }
+
+            Rule::map_literal => {
+                let entries = pair
+                    .into_inner()
+                    .filter_map(|entry| {
+                        let mut entry_pairs = entry.into_inner();
+                        let key = entry_pairs.next()?.as_str().to_string();
+                        let value = Value::from_pair(entry_pairs.next()?);
+
+                        Some((key, value))
+                    })
+                    .collect();
+
+                Value::Map(entries)
+            }
+
+            Rule::del_val_length => {
+                let text = pair.as_str();
+                let unit = pair
+                    .into_inner()
+                    .find(|p| p.as_rule() == Rule::length_type)
+                    .map_or("", |p| p.as_str());
+                let number = text[..text.len() - unit.len()].parse::<f64>();
+
+                match number {
+                    Ok(number) if unit.is_empty() => Value::Number(number),
+                    Ok(number) => Value::Length(number, unit.to_string()),
+                    Err(_) => Value::Raw(text.to_string()),
+                }
+            }
+
+            Rule::del_val_color => Value::Color(pair.as_str().to_string()),
+
+            Rule::del_val_keyword => Value::Keyword(pair.as_str().to_string()),
+
+            Rule::string_literal => {
+                let text = pair.as_str();
+                Value::String(text[1..text.len() - 1].to_string())
+            }
+
+            _ => Value::Raw(pair.as_str().trim().to_string()),
+        }
+    }
+
+    /// Parses CSS text into a typed value, falling back to `Value::Raw`
+    pub fn parse(text: &str) -> Value {
+        match RCSSParser::parse(Rule::variable_value_body, text) {
+            Ok(mut pairs) =>
+                match pairs.next() {
+                    Some(pair) if !pair.clone().into_inner().flatten().any(is_unresolved) => {
+                        Value::from_pair(pair)
+                    }
+                    _ => Value::Raw(text.trim().to_string()),
+                }
+            Err(_) => Value::Raw(text.trim().to_string()),
+        }
+    }
+
+    /// The name used for this kind of value in return types and error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Length(_, unit) if unit == "%" => "percentage",
+            Value::Length(..) => "length",
+            Value::Color(_) => "color",
+            Value::String(_) => "string",
+            Value::Keyword(_) => "keyword",
+            Value::Calc(_) => "calc",
+            Value::List(..) => "list",
+            Value::Map(_) => "map",
+            Value::Raw(_) => "raw",
+        }
+    }
+}
+
+/// Function calls, references and arithmetic are kept as raw text rather than typed
+fn is_unresolved(pair: Pair<Rule>) -> bool {
+    matches!(
+        pair.as_rule(),
+        Rule::function_call |
+            Rule::variable_reference |
+            Rule::arithmetic_operator |
+            Rule::value_group |
+            Rule::closure
+    )
+}
+
+/// Formats a number the way it should appear in CSS (ex: 2 rather than 2.0)
+pub fn format_number(number: f64) -> String {
+    let rounded = (number * 10_000.0).round() / 10_000.0;
+
+    if rounded == rounded.trunc() {
+        format!("{}", rounded as i64)
+    } else {
+        format!("{}", rounded)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", format_number(*number)),
+            Value::Length(number, unit) => write!(f, "{}{}", format_number(*number), unit),
+            Value::Color(color) => write!(f, "{}", color),
+            Value::String(string) => write!(f, "\"{}\"", string.replace('"', "\\\"")),
+            Value::Keyword(keyword) => write!(f, "{}", keyword),
+            Value::Calc(expression) => write!(f, "calc({})", expression),
+            Value::Raw(raw) => write!(f, "{}", raw),
+            Value::List(values, separator) => {
+                let separator = match separator {
+                    ListSeparator::Space => " ",
+                    ListSeparator::Comma => ", ",
+                };
+
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect();
+
+                write!(f, "{}", values.join(separator))
+            }
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+
+                write!(f, "{{ {} }}", entries.join(", "))
+            }
+        }
+    }
+}